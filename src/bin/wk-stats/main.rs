@@ -0,0 +1,224 @@
+use chrono::{DateTime, Local};
+use clap::Parser;
+use governor::{Quota, RateLimiter};
+use std::io::{self, IsTerminal, Write};
+use std::num::NonZeroU32;
+use std::process::ExitCode;
+use std::time::Duration;
+use tokio::sync::watch;
+use wanikani_stats::data_processing::report::{AccuracyRow, Cell, StatsSummary};
+use wanikani_stats::data_processing::{ApiClient, FetchProgress, DEFAULT_BASE_URL};
+
+mod tui;
+
+/// WaniKani allows 60 requests a minute per token. The server shares that between everyone using it, but
+/// here there is only ever the one token, so the whole allowance can go to it.
+const REQUESTS_PER_MINUTE: u32 = 60;
+
+/// Prints your WaniKani stats, the same ones the /info page shows, without running the web server.
+#[derive(Parser)]
+#[command(name = "wk-stats", version)]
+struct Args {
+    /// Your WaniKani API token. It only needs read access.
+    #[arg(long, env = "WANIKANI_API_TOKEN", hide_env_values = true)]
+    token: String,
+
+    /// Print everything as JSON.
+    #[arg(long, conflicts_with_all = ["csv", "tui"])]
+    json: bool,
+
+    /// Print the accuracy table as CSV.
+    #[arg(long, conflicts_with = "tui")]
+    csv: bool,
+
+    /// Open a live dashboard with the accuracy table, SRS stages, review forecast and leeches.
+    #[arg(long)]
+    tui: bool,
+
+    /// How often the dashboard brings itself up to date, in seconds.
+    #[arg(long, default_value_t = 300, value_parser = clap::value_parser!(u64).range(30..))]
+    interval: u64,
+
+    /// Where the WaniKani API lives, for pointing this at a proxy or a mock server.
+    #[arg(long, default_value = DEFAULT_BASE_URL)]
+    base_url: String,
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> ExitCode {
+    let args = Args::parse();
+
+    let client = reqwest::Client::new();
+    let limiter = RateLimiter::direct(Quota::per_minute(
+        NonZeroU32::new(REQUESTS_PER_MINUTE).unwrap(),
+    ));
+
+    if args.tui {
+        let (progress_sender, _) = watch::channel(FetchProgress::default());
+        let api_client = ApiClient::new(args.token, &client, &limiter)
+            .with_base_url(&args.base_url)
+            .with_progress(&progress_sender);
+
+        return match tui::run(
+            &api_client,
+            &progress_sender,
+            Duration::from_secs(args.interval),
+        )
+        .await
+        {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(err) => {
+                eprintln!("wk-stats: {}", err);
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    // big accounts take a while to fetch, so say how far along it is as long as someone is there to read it
+    let (progress_sender, progress) = watch::channel(FetchProgress::default());
+    let show_progress = io::stderr().is_terminal();
+    let reporter = tokio::spawn(report_progress(progress, show_progress));
+
+    let result = ApiClient::new(args.token, &client, &limiter)
+        .with_base_url(&args.base_url)
+        .with_progress(&progress_sender)
+        .build_complete_user_info()
+        .await;
+
+    drop(progress_sender);
+    let _ = reporter.await;
+
+    let user_info = match result {
+        Ok(user_info) => user_info,
+        Err(err) => {
+            eprintln!("wk-stats: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let summary = StatsSummary::from(&user_info);
+    let printed = if args.json {
+        print_json(&summary)
+    } else if args.csv {
+        print_csv(&summary.accuracy)
+    } else {
+        print_text(&summary)
+    };
+
+    match printed {
+        Ok(()) => ExitCode::SUCCESS,
+        // the usual reason for this is being piped into something like `head`, which isn't worth complaining about
+        Err(err) if err.kind() == io::ErrorKind::BrokenPipe => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("wk-stats: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn report_progress(mut progress: watch::Receiver<FetchProgress>, show: bool) {
+    while progress.changed().await.is_ok() {
+        if show {
+            eprint!("\r\x1b[K{}", *progress.borrow_and_update());
+        }
+    }
+
+    if show {
+        eprint!("\r\x1b[K");
+    }
+}
+
+fn print_json(summary: &StatsSummary) -> io::Result<()> {
+    let mut stdout = io::stdout().lock();
+
+    serde_json::to_writer_pretty(&mut stdout, summary)?;
+    writeln!(stdout)
+}
+
+/// Percentages go out as plain numbers so spreadsheets treat them as numbers, and the empty radical
+/// reading is left blank.
+fn print_csv(rows: &[AccuracyRow]) -> io::Result<()> {
+    let mut writer = csv::Writer::from_writer(io::stdout().lock());
+    let plain = |cell: Cell| match cell {
+        Cell::Count(count) => count.to_string(),
+        Cell::Percent(percent) => format!("{:.2}", percent),
+        Cell::Empty => String::new(),
+    };
+
+    writer.write_record(["row", "reading", "meaning", "total"])?;
+
+    for row in rows {
+        writer.write_record([
+            row.label.to_string(),
+            plain(row.reading),
+            plain(row.meaning),
+            plain(row.total),
+        ])?;
+    }
+
+    writer.flush()
+}
+
+/// The same list and table as the /info page, laid out for a terminal.
+fn print_text(summary: &StatsSummary) -> io::Result<()> {
+    let mut stdout = io::stdout().lock();
+    let now = Local::now();
+    let days_since = |date: &DateTime<Local>| (now - date).num_days();
+    let reset_date = summary.most_recent_reset.unwrap_or(summary.started_at);
+
+    writeln!(stdout, "User: {}", summary.username)?;
+    writeln!(stdout, "Level: {}", summary.level)?;
+    writeln!(
+        stdout,
+        "Started At: {} ({} days ago)",
+        summary.started_at.format("%Y-%m-%d"),
+        days_since(&summary.started_at)
+    )?;
+    writeln!(stdout, "Number of Resets: {}", summary.reset_count)?;
+    writeln!(
+        stdout,
+        "Most Recent Reset: {} ({} days ago)",
+        reset_date.format("%Y-%m-%d"),
+        days_since(&reset_date)
+    )?;
+    writeln!(stdout, "Kanji Learned: {}", summary.kanji_learned)?;
+    writeln!(stdout, "Vocabulary Learned: {}", summary.vocab_learned)?;
+    writeln!(
+        stdout,
+        "Kana Vocabulary Learned: {}",
+        summary.kana_vocab_learned
+    )?;
+    writeln!(
+        stdout,
+        "All Vocabulary Learned: {}",
+        summary.all_vocab_learned
+    )?;
+    writeln!(stdout, "Radicals Learned: {}", summary.radicals_learned)?;
+    writeln!(stdout)?;
+
+    let label_width = summary
+        .accuracy
+        .iter()
+        .map(|row| row.label.len() + 1)
+        .max()
+        .unwrap_or(0);
+
+    writeln!(
+        stdout,
+        "{:label_width$}  {:>10}  {:>10}  {:>10}",
+        "", "Reading", "Meaning", "Total"
+    )?;
+
+    for row in &summary.accuracy {
+        writeln!(
+            stdout,
+            "{:label_width$}  {:>10}  {:>10}  {:>10}",
+            format!("{}:", row.label),
+            row.reading.to_string(),
+            row.meaning.to_string(),
+            row.total.to_string()
+        )?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,472 @@
+use chrono::Local;
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Modifier, Style},
+    text::Line,
+    widgets::{Bar, BarChart, BarGroup, Block, Paragraph, Row, Table, TableState},
+    Frame,
+};
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::time::Duration;
+use tokio::sync::{mpsc, watch};
+use wanikani_stats::data_processing::report::{
+    accuracy_table, leeches, review_forecast, srs_distribution, ReviewForecast,
+};
+use wanikani_stats::data_processing::{ApiClient, CompleteUserInfo, FetchProgress, WaniKaniError};
+
+/// How far ahead the forecast pane looks.
+const FORECAST_HOURS: usize = 24;
+
+type Fetch<'a> = Pin<Box<dyn Future<Output = Result<CompleteUserInfo, WaniKaniError>> + 'a>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Pane {
+    Accuracy,
+    Srs,
+    Forecast,
+    Leeches,
+}
+
+impl Pane {
+    const ALL: [Pane; 4] = [Pane::Accuracy, Pane::Srs, Pane::Forecast, Pane::Leeches];
+
+    fn next(self) -> Pane {
+        Pane::ALL[(self as usize + 1) % Pane::ALL.len()]
+    }
+
+    fn previous(self) -> Pane {
+        Pane::ALL[(self as usize + Pane::ALL.len() - 1) % Pane::ALL.len()]
+    }
+
+    fn title(self) -> &'static str {
+        match self {
+            Pane::Accuracy => " Accuracy ",
+            Pane::Srs => " SRS Stages ",
+            Pane::Forecast => " Review Forecast ",
+            Pane::Leeches => " Leeches ",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Action {
+    Nothing,
+    Refresh,
+    Quit,
+}
+
+/// What the dashboard is showing and where the user is in it. The data itself is only ever replaced
+/// wholesale when a fetch finishes, so a failed refresh leaves the last good numbers on screen.
+struct Dashboard {
+    info: Option<CompleteUserInfo>,
+    status: String,
+    focus: Pane,
+    forecast: TableState,
+    leeches: TableState,
+}
+
+impl Dashboard {
+    fn new() -> Self {
+        Dashboard {
+            info: None,
+            status: FetchProgress::default().to_string(),
+            focus: Pane::Accuracy,
+            forecast: TableState::default(),
+            leeches: TableState::default(),
+        }
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) -> Action {
+        if key.kind != KeyEventKind::Press {
+            return Action::Nothing;
+        }
+
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Action::Quit,
+            // raw mode means Ctrl+C arrives as a key instead of a signal
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                return Action::Quit
+            }
+            KeyCode::Char('r') => return Action::Refresh,
+            KeyCode::Tab | KeyCode::Right | KeyCode::Char('l') => self.focus = self.focus.next(),
+            KeyCode::BackTab | KeyCode::Left | KeyCode::Char('h') => {
+                self.focus = self.focus.previous()
+            }
+            KeyCode::Down | KeyCode::Char('j') => {
+                if let Some(state) = self.focused_table() {
+                    state.select_next();
+                }
+            }
+            KeyCode::Up | KeyCode::Char('k') => {
+                if let Some(state) = self.focused_table() {
+                    state.select_previous();
+                }
+            }
+            KeyCode::Home | KeyCode::Char('g') => {
+                if let Some(state) = self.focused_table() {
+                    state.select_first();
+                }
+            }
+            _ => {}
+        }
+
+        Action::Nothing
+    }
+
+    fn focused_table(&mut self) -> Option<&mut TableState> {
+        match self.focus {
+            Pane::Forecast => Some(&mut self.forecast),
+            Pane::Leeches => Some(&mut self.leeches),
+            Pane::Accuracy | Pane::Srs => None,
+        }
+    }
+
+    fn finish_fetch(&mut self, result: Result<CompleteUserInfo, WaniKaniError>) {
+        match result {
+            Ok(info) => {
+                self.status = format!("Updated at {}", Local::now().format("%H:%M:%S"));
+                self.info = Some(info);
+            }
+            Err(err) => self.status = err.to_string(),
+        }
+    }
+
+    fn block(&self, pane: Pane) -> Block<'static> {
+        let block = Block::bordered().title(pane.title());
+
+        if pane == self.focus {
+            block.border_style(Style::new().fg(Color::Yellow))
+        } else {
+            block
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [header, body, footer] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Min(0),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [top, bottom] =
+            Layout::vertical([Constraint::Percentage(50), Constraint::Percentage(50)]).areas(body);
+        let [accuracy, srs] =
+            Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)]).areas(top);
+        let [forecast, leech_list] =
+            Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .areas(bottom);
+
+        let title = match &self.info {
+            Some(info) => format!(
+                "{}  level {}  ·  {}",
+                info.get_user_name(),
+                info.get_level(),
+                self.status
+            ),
+            None => self.status.clone(),
+        };
+
+        frame.render_widget(
+            Paragraph::new(title).style(Style::new().add_modifier(Modifier::BOLD)),
+            header,
+        );
+        frame.render_widget(
+            Paragraph::new("Tab/←→ switch pane  ↑↓ scroll  r refresh  q quit".to_string())
+                .style(Style::new().fg(Color::DarkGray)),
+            footer,
+        );
+
+        let Some(info) = self.info.clone() else {
+            for (pane, area) in Pane::ALL
+                .into_iter()
+                .zip([accuracy, srs, forecast, leech_list])
+            {
+                frame.render_widget(Paragraph::new("Loading…").block(self.block(pane)), area);
+            }
+
+            return;
+        };
+
+        self.draw_accuracy(frame, accuracy, &info);
+        self.draw_srs(frame, srs, &info);
+        self.draw_forecast(frame, forecast, &info);
+        self.draw_leeches(frame, leech_list, &info);
+    }
+
+    fn draw_accuracy(&self, frame: &mut Frame, area: Rect, info: &CompleteUserInfo) {
+        let rows = accuracy_table(info).into_iter().map(|row| {
+            Row::new([
+                format!("{}:", row.label),
+                row.reading.to_string(),
+                row.meaning.to_string(),
+                row.total.to_string(),
+            ])
+        });
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Length(17),
+                Constraint::Length(9),
+                Constraint::Length(9),
+                Constraint::Length(9),
+            ],
+        )
+        .header(
+            Row::new(["", "Reading", "Meaning", "Total"])
+                .style(Style::new().add_modifier(Modifier::BOLD)),
+        )
+        .block(self.block(Pane::Accuracy));
+
+        frame.render_widget(table, area);
+    }
+
+    fn draw_srs(&self, frame: &mut Frame, area: Rect, info: &CompleteUserInfo) {
+        let bars: Vec<Bar> = srs_distribution(info)
+            .into_iter()
+            .map(|(group, count)| {
+                Bar::default()
+                    .label(Line::from(group.to_string()))
+                    .value(count.max(0) as u64)
+            })
+            .collect();
+        let chart = BarChart::default()
+            .data(BarGroup::default().bars(&bars))
+            .bar_width(11)
+            .bar_gap(1)
+            .bar_style(Style::new().fg(Color::Magenta))
+            .block(self.block(Pane::Srs));
+
+        frame.render_widget(chart, area);
+    }
+
+    fn draw_forecast(&mut self, frame: &mut Frame, area: Rect, info: &CompleteUserInfo) {
+        let forecast = review_forecast(info, Local::now(), FORECAST_HOURS);
+        let table = self.forecast_table(&forecast);
+
+        frame.render_stateful_widget(table, area, &mut self.forecast);
+    }
+
+    /// The "Now" row only has what is waiting already. The running total starts with the first hour, which
+    /// counts those reviews in too.
+    fn forecast_table(&self, forecast: &ReviewForecast) -> Table<'static> {
+        let now = Row::new([
+            "Now".to_string(),
+            forecast.available_now.to_string(),
+            String::new(),
+        ]);
+        let rows = std::iter::once(now).chain(forecast.hours.iter().map(|hour| {
+            Row::new([
+                hour.starts_at.format("%a %H:00").to_string(),
+                format!("+{}", hour.reviews),
+                hour.cumulative.to_string(),
+            ])
+        }));
+        Table::new(
+            rows,
+            [
+                Constraint::Length(10),
+                Constraint::Length(8),
+                Constraint::Length(8),
+            ],
+        )
+        .header(Row::new(["When", "Due", "Total"]).style(Style::new().add_modifier(Modifier::BOLD)))
+        .row_highlight_style(self.highlight(Pane::Forecast))
+        .block(self.block(Pane::Forecast))
+    }
+
+    fn draw_leeches(&mut self, frame: &mut Frame, area: Rect, info: &CompleteUserInfo) {
+        let found = leeches(info);
+        let rows = found.iter().map(|leech| {
+            Row::new([
+                leech.characters.clone(),
+                leech.meaning.clone(),
+                leech.subject_type.replace('_', " "),
+                leech.incorrect.to_string(),
+                format!("{:.1}", leech.score),
+            ])
+        });
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Length(10),
+                Constraint::Min(10),
+                Constraint::Length(15),
+                Constraint::Length(6),
+                Constraint::Length(6),
+            ],
+        )
+        .header(
+            Row::new(["Subject", "Meaning", "Type", "Wrong", "Score"])
+                .style(Style::new().add_modifier(Modifier::BOLD)),
+        )
+        .row_highlight_style(self.highlight(Pane::Leeches))
+        .block(
+            self.block(Pane::Leeches)
+                .title_bottom(format!(" {} ", found.len())),
+        );
+
+        frame.render_stateful_widget(table, area, &mut self.leeches);
+    }
+
+    /// The selected row is only highlighted in the pane that has focus, so it is clear which one the
+    /// arrow keys will move.
+    fn highlight(&self, pane: Pane) -> Style {
+        if pane == self.focus {
+            Style::new().add_modifier(Modifier::REVERSED)
+        } else {
+            Style::new()
+        }
+    }
+}
+
+/// Key presses are read on a thread of their own since crossterm only offers a blocking read, and
+/// handed to the async side over a channel.
+fn read_keys() -> mpsc::UnboundedReceiver<KeyEvent> {
+    let (sender, keys) = mpsc::unbounded_channel();
+
+    std::thread::spawn(move || {
+        while let Ok(event) = event::read() {
+            if let Event::Key(key) = event {
+                if sender.send(key).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+
+    keys
+}
+
+/// Runs the dashboard until the user quits. The first load fetches everything, and after that the data
+/// is brought up to date every `interval`, or whenever `r` is pressed, with the same incremental refresh
+/// the server uses. `progress` has to be the sender `api_client` reports its pages to.
+pub async fn run(
+    api_client: &ApiClient<'_>,
+    progress: &watch::Sender<FetchProgress>,
+    interval: Duration,
+) -> io::Result<()> {
+    let mut terminal = ratatui::init();
+    let mut dashboard = Dashboard::new();
+    let mut keys = read_keys();
+    let mut progress_updates = progress.subscribe();
+    let mut ticker = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+    let mut fetch: Option<Fetch> = Some(Box::pin(api_client.build_complete_user_info()));
+
+    let result = loop {
+        if let Err(err) = terminal.draw(|frame| dashboard.draw(frame)) {
+            break Err(err);
+        }
+
+        let refresh = tokio::select! {
+            Some(key) = keys.recv() => match dashboard.handle_key(key) {
+                Action::Quit => break Ok(()),
+                Action::Refresh => true,
+                Action::Nothing => false,
+            },
+            _ = ticker.tick() => true,
+            result = async { fetch.as_mut().unwrap().await }, if fetch.is_some() => {
+                fetch = None;
+                dashboard.finish_fetch(result);
+                false
+            }
+            Ok(()) = progress_updates.changed(), if fetch.is_some() => {
+                dashboard.status = progress_updates.borrow_and_update().to_string();
+                false
+            }
+        };
+
+        if refresh && fetch.is_none() {
+            progress.send_replace(FetchProgress::default());
+            dashboard.status = "Refreshing…".to_string();
+
+            fetch = Some(match dashboard.info.clone() {
+                Some(previous) => {
+                    Box::pin(async move { api_client.refresh_complete_user_info(&previous).await })
+                }
+                None => Box::pin(api_client.build_complete_user_info()),
+            });
+        }
+    };
+
+    ratatui::restore();
+
+    result
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use chrono::TimeZone;
+    use ratatui::{buffer::Buffer, widgets::Widget};
+    use wanikani_stats::data_processing::report::ForecastHour;
+
+    fn press(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    #[test]
+    fn test_tab_cycles_through_every_pane() {
+        let mut dashboard = Dashboard::new();
+
+        for expected in [Pane::Srs, Pane::Forecast, Pane::Leeches, Pane::Accuracy] {
+            dashboard.handle_key(press(KeyCode::Tab));
+
+            assert_eq!(dashboard.focus, expected);
+        }
+
+        dashboard.handle_key(press(KeyCode::BackTab));
+
+        assert_eq!(dashboard.focus, Pane::Leeches);
+    }
+
+    #[test]
+    fn test_forecast_now_row_leaves_total_blank() {
+        let dashboard = Dashboard::new();
+        let forecast = ReviewForecast {
+            available_now: 5,
+            hours: vec![ForecastHour {
+                starts_at: Local.with_ymd_and_hms(2024, 1, 1, 9, 0, 0).unwrap(),
+                reviews: 3,
+                cumulative: 8,
+            }],
+        };
+        let area = Rect::new(0, 0, 30, 5);
+        let mut buffer = Buffer::empty(area);
+
+        Widget::render(dashboard.forecast_table(&forecast), area, &mut buffer);
+
+        let line = |y: u16| {
+            (0..area.width)
+                .map(|x| buffer[(x, y)].symbol())
+                .collect::<String>()
+        };
+
+        assert_eq!(line(2).trim_end_matches([' ', '│']), "│Now        5");
+        assert_eq!(
+            line(3).trim_end_matches([' ', '│']),
+            "│Mon 09:00  +3       8"
+        );
+    }
+
+    #[test]
+    fn test_keys_map_to_actions() {
+        let mut dashboard = Dashboard::new();
+
+        assert_eq!(
+            dashboard.handle_key(press(KeyCode::Char('r'))),
+            Action::Refresh
+        );
+        assert_eq!(
+            dashboard.handle_key(press(KeyCode::Char('q'))),
+            Action::Quit
+        );
+        assert_eq!(
+            dashboard.handle_key(KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL)),
+            Action::Quit
+        );
+    }
+}
@@ -0,0 +1,295 @@
+use crate::{UserToken, LONG_IDLE_TIMEOUT};
+use chrono::{DateTime, Local, NaiveDate, Utc};
+use moka::future::Cache;
+use std::collections::BTreeMap;
+use uuid::Uuid;
+use wanikani_stats::data_processing::{report::review_forecast, CompleteUserInfo};
+
+/// How many reviews an hour needs before it shows up in the feed, unless the user picks something else.
+pub const DEFAULT_THRESHOLD: i32 = 50;
+
+/// How far ahead the feed looks. WaniKani's longest interval before burning is months, but the next week
+/// is all anyone plans around.
+const FORECAST_DAYS: usize = 7;
+
+/// What a feed secret stands for. The secret goes into a URL that gets pasted into calendar apps, so it
+/// is what a calendar app authenticates with instead of a session cookie, and the token itself never
+/// leaves the server.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CalendarFeed {
+    pub token: UserToken,
+    pub threshold: i32,
+}
+
+/// Every calendar feed that has been handed out, looked up by its secret, along with which secret each
+/// token has so the settings page can show it again. A feed no calendar app has asked for in
+/// `LONG_IDLE_TIMEOUT` is turned off, and past `capacity` feeds the least used ones go first.
+#[derive(Clone)]
+pub struct CalendarStore {
+    feeds: Cache<String, CalendarFeed>,
+    secrets: Cache<UserToken, String>,
+}
+
+impl CalendarStore {
+    pub fn new(capacity: u64) -> Self {
+        CalendarStore {
+            feeds: Cache::builder()
+                .max_capacity(capacity)
+                .time_to_idle(LONG_IDLE_TIMEOUT)
+                .build(),
+            secrets: Cache::builder()
+                .max_capacity(capacity)
+                .time_to_idle(LONG_IDLE_TIMEOUT)
+                .build(),
+        }
+    }
+
+    /// The feed behind a secret. Reading a feed keeps the secret alive on both sides, since a calendar
+    /// app that keeps polling is someone who still wants it.
+    pub async fn feed(&self, secret: &str) -> Option<CalendarFeed> {
+        let feed = self.feeds.get(secret).await?;
+        self.secrets.get(&feed.token).await;
+
+        Some(feed)
+    }
+
+    /// The user's secret and threshold, if they have a feed.
+    pub async fn for_token(&self, token: &UserToken) -> Option<(String, i32)> {
+        let secret = self.secrets.get(token).await?;
+        let feed = self.feeds.get(&secret).await?;
+
+        Some((secret, feed.threshold))
+    }
+
+    /// Turns the feed on with the given threshold and returns its secret. Someone who already has a feed
+    /// keeps their secret, so changing the threshold doesn't mean subscribing again in every calendar.
+    pub async fn subscribe(&self, token: &UserToken, threshold: i32) -> String {
+        let secret = match self.for_token(token).await {
+            Some((secret, _)) => secret,
+            None => Uuid::new_v4().simple().to_string(),
+        };
+        let feed = CalendarFeed {
+            token: token.clone(),
+            threshold,
+        };
+
+        self.feeds.insert(secret.clone(), feed).await;
+        self.secrets.insert(token.clone(), secret.clone()).await;
+
+        secret
+    }
+
+    /// Turns the feed off. Calendars that subscribed to it get a 404 from then on.
+    pub async fn revoke(&self, token: &UserToken) {
+        if let Some(secret) = self.secrets.remove(token).await {
+            self.feeds.invalidate(&secret).await;
+        }
+    }
+
+    /// Settles both the feeds and the secrets pointing at them, for `AppState::flush_stores`.
+    pub async fn flush(&self) {
+        self.feeds.run_pending_tasks().await;
+        self.secrets.run_pending_tasks().await;
+    }
+}
+
+/// The feed itself: an event for every hour in the next week where at least `threshold` reviews come due,
+/// and an all day event for each day with the total. Every event has a UID made from the user and the time
+/// it covers, so calendar apps update the events they already have when the forecast changes instead of
+/// piling up new ones.
+pub fn render_feed(info: &CompleteUserInfo, threshold: i32, now: DateTime<Local>) -> String {
+    let forecast = review_forecast(info, now, FORECAST_DAYS * 24);
+    let username = info.get_user_name();
+    let stamp = ics_time(now);
+    let mut days: BTreeMap<NaiveDate, (i32, i32, DateTime<Local>)> = BTreeMap::new();
+    let mut ics = String::new();
+
+    content_line(&mut ics, "BEGIN", "VCALENDAR");
+    content_line(&mut ics, "VERSION", "2.0");
+    content_line(&mut ics, "PRODID", "-//wanikani_stats//review forecast//EN");
+    content_line(&mut ics, "CALSCALE", "GREGORIAN");
+    content_line(&mut ics, "X-WR-CALNAME", "WaniKani reviews");
+    content_line(&mut ics, "REFRESH-INTERVAL;VALUE=DURATION", "PT1H");
+    content_line(&mut ics, "X-PUBLISHED-TTL", "PT1H");
+
+    for hour in &forecast.hours {
+        let (total, busiest, busiest_at) =
+            days.entry(hour.starts_at.date_naive())
+                .or_insert((0, 0, hour.starts_at));
+        *total += hour.reviews;
+
+        if hour.reviews > *busiest {
+            *busiest = hour.reviews;
+            *busiest_at = hour.starts_at;
+        }
+
+        if hour.reviews < threshold.max(1) {
+            continue;
+        }
+
+        content_line(&mut ics, "BEGIN", "VEVENT");
+        content_line(
+            &mut ics,
+            "UID",
+            &format!(
+                "hour-{}-{}@wanikani_stats",
+                ics_time(hour.starts_at),
+                username
+            ),
+        );
+        content_line(&mut ics, "DTSTAMP", &stamp);
+        content_line(&mut ics, "DTSTART", &ics_time(hour.starts_at));
+        content_line(
+            &mut ics,
+            "DTEND",
+            &ics_time(hour.starts_at + chrono::Duration::hours(1)),
+        );
+        content_line(
+            &mut ics,
+            "SUMMARY",
+            &escape(&format!("{} WaniKani reviews", hour.reviews)),
+        );
+        content_line(
+            &mut ics,
+            "DESCRIPTION",
+            &escape(&format!(
+                "{} reviews come due this hour, {} in total if none get done before then.",
+                hour.reviews, hour.cumulative
+            )),
+        );
+        content_line(&mut ics, "TRANSP", "TRANSPARENT");
+        content_line(&mut ics, "END", "VEVENT");
+    }
+
+    // whatever is already waiting is due today along with everything else
+    if let Some((total, _, _)) = days.values_mut().next() {
+        *total += forecast.available_now;
+    }
+
+    for (day, (total, busiest, busiest_at)) in days {
+        if total == 0 {
+            continue;
+        }
+
+        let date = day.format("%Y%m%d").to_string();
+
+        content_line(&mut ics, "BEGIN", "VEVENT");
+        content_line(
+            &mut ics,
+            "UID",
+            &format!("day-{}-{}@wanikani_stats", date, username),
+        );
+        content_line(&mut ics, "DTSTAMP", &stamp);
+        content_line(&mut ics, "DTSTART;VALUE=DATE", &date);
+        content_line(
+            &mut ics,
+            "DTEND;VALUE=DATE",
+            &(day + chrono::Duration::days(1))
+                .format("%Y%m%d")
+                .to_string(),
+        );
+        content_line(
+            &mut ics,
+            "SUMMARY",
+            &escape(&format!("{} WaniKani reviews today", total)),
+        );
+
+        if busiest > 0 {
+            content_line(
+                &mut ics,
+                "DESCRIPTION",
+                &escape(&format!(
+                    "The busiest hour starts at {} with {} reviews.",
+                    busiest_at.format("%H:%M"),
+                    busiest
+                )),
+            );
+        }
+
+        content_line(&mut ics, "TRANSP", "TRANSPARENT");
+        content_line(&mut ics, "END", "VEVENT");
+    }
+
+    content_line(&mut ics, "END", "VCALENDAR");
+
+    ics
+}
+
+fn ics_time(time: DateTime<Local>) -> String {
+    time.with_timezone(&Utc)
+        .format("%Y%m%dT%H%M%SZ")
+        .to_string()
+}
+
+/// Escapes the characters that mean something in an iCalendar text value.
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+/// Adds one `NAME:value` line. iCalendar wants lines ended with CRLF and no longer than 75 bytes, with
+/// anything past that carried on in lines that start with a space. Lines are only ever split between
+/// characters, never in the middle of one.
+fn content_line(ics: &mut String, name: &str, value: &str) {
+    let line = format!("{}:{}", name, value);
+    let mut length = 0;
+
+    for c in line.chars() {
+        if length + c.len_utf8() > 75 {
+            ics.push_str("\r\n ");
+            length = 1;
+        }
+
+        ics.push(c);
+        length += c.len_utf8();
+    }
+
+    ics.push_str("\r\n");
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn test_long_lines_are_folded() {
+        let mut ics = String::new();
+
+        content_line(&mut ics, "DESCRIPTION", &"日".repeat(40));
+
+        let lines: Vec<&str> = ics.split("\r\n").collect();
+
+        assert!(lines.iter().all(|line| line.len() <= 75));
+        assert!(lines[1].starts_with(' '));
+        assert_eq!(
+            ics.replace("\r\n ", ""),
+            format!("DESCRIPTION:{}\r\n", "日".repeat(40))
+        );
+    }
+
+    #[test]
+    fn test_text_is_escaped() {
+        assert_eq!(escape("one, two; three\\"), "one\\, two\\; three\\\\");
+    }
+
+    #[tokio::test]
+    async fn test_resubscribing_keeps_the_secret_and_revoking_drops_it() {
+        let store = CalendarStore::new(10);
+        let token = UserToken {
+            token: "token".to_string(),
+        };
+
+        let secret = store.subscribe(&token, 20).await;
+
+        assert_eq!(store.subscribe(&token, 80).await, secret);
+        assert_eq!(store.feed(&secret).await.unwrap().threshold, 80);
+
+        store.revoke(&token).await;
+
+        assert_eq!(store.feed(&secret).await, None);
+        assert_eq!(store.for_token(&token).await, None);
+    }
+}
@@ -0,0 +1,561 @@
+use serde::Deserialize;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use wanikani_stats::data_processing::DEFAULT_BASE_URL;
+
+/// The config file that is read if `WK_STATS_CONFIG` doesn't point somewhere else. It's fine for it not to exist.
+const DEFAULT_CONFIG_PATH: &str = "wanikani_stats.toml";
+
+/// Everything about the server that used to be hardcoded in `main`. Values come from, in order of priority,
+/// `WK_STATS_*` environment variables (a `.env` file counts), the TOML config file, and then the defaults
+/// below, which are the values the server always ran with before this existed.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub listen_addr: SocketAddr,
+    pub wanikani_base_url: String,
+    /// How long a shutdown waits for open requests and WaniKani fetches to finish before giving up on them.
+    pub shutdown_drain_secs: u64,
+    /// Where people reach the site, like `https://stats.example.com`. Links that are meant to be copied out of the
+    /// site, like calendar feeds, are built on this. Without it they are built from the `Host` header of the request.
+    pub public_url: Option<String>,
+    pub cache: CacheConfig,
+    pub rate_limit: RateLimitConfig,
+    pub session: SessionConfig,
+    pub stores: StoreConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    pub capacity: u64,
+    pub user_info_ttl_secs: u64,
+    pub refresh_cooldown_secs: u64,
+    pub active_user_idle_secs: u64,
+    pub background_refresh_interval_secs: u64,
+    pub background_refresh_margin_secs: u64,
+    /// The most users the background refresh gets to per pass. It shares the rate limiter with people loading
+    /// pages, so this keeps it from taking all of it when a lot of entries come up for expiry together.
+    pub background_refresh_batch: usize,
+    pub load_failure_ttl_secs: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    pub requests_per_minute: u32,
+}
+
+/// How many entries each of the smaller in-memory stores keeps before it starts dropping the least used ones.
+/// Each holds at most one entry per user (or, for groups, one per group and one per member), so these only
+/// need raising along with `session.capacity` on a busy site.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StoreConfig {
+    pub calendar_capacity: u64,
+    pub profile_capacity: u64,
+    pub group_capacity: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionConfig {
+    pub capacity: u64,
+    pub idle_timeout_secs: u64,
+    /// Encrypts the session and flash cookies. It has to be at least 64 bytes long. Without one a random
+    /// key is generated at startup, which logs everyone out whenever the server restarts.
+    pub key: Option<String>,
+    pub cookie_name: String,
+    pub cookie_path: String,
+    pub cookie_domain: Option<String>,
+    pub cookie_secure: bool,
+    pub cookie_same_site: String,
+    pub cookie_max_age_secs: Option<u64>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            listen_addr: SocketAddr::from(([0, 0, 0, 0], 3000)),
+            wanikani_base_url: DEFAULT_BASE_URL.to_string(),
+            shutdown_drain_secs: 30,
+            public_url: None,
+            cache: CacheConfig::default(),
+            rate_limit: RateLimitConfig::default(),
+            session: SessionConfig::default(),
+            stores: StoreConfig::default(),
+        }
+    }
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            capacity: 1000,
+            user_info_ttl_secs: 300,
+            refresh_cooldown_secs: 120,
+            active_user_idle_secs: 30 * 60,
+            background_refresh_interval_secs: 30,
+            background_refresh_margin_secs: 90,
+            background_refresh_batch: 2,
+            load_failure_ttl_secs: 60,
+        }
+    }
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            requests_per_minute: 10,
+        }
+    }
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        SessionConfig {
+            capacity: 10_000,
+            idle_timeout_secs: 14 * 24 * 60 * 60,
+            key: None,
+            cookie_name: "user_uuid".to_string(),
+            cookie_path: "/".to_string(),
+            cookie_domain: None,
+            cookie_secure: false,
+            cookie_same_site: "lax".to_string(),
+            cookie_max_age_secs: None,
+        }
+    }
+}
+
+impl Default for StoreConfig {
+    fn default() -> Self {
+        StoreConfig {
+            calendar_capacity: 10_000,
+            profile_capacity: 10_000,
+            group_capacity: 10_000,
+        }
+    }
+}
+
+/// Why the config couldn't be loaded. The server refuses to start on any of these rather than quietly
+/// falling back to a default the operator didn't ask for.
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Env(&'static str, String),
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, err) => write!(f, "could not read {}: {}", path.display(), err),
+            ConfigError::Parse(path, err) => {
+                write!(f, "could not parse {}: {}", path.display(), err)
+            }
+            ConfigError::Env(var, value) => write!(f, "{} has an invalid value: {:?}", var, value),
+            ConfigError::Invalid(reason) => write!(f, "invalid config: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    /// Loads the config the server should run with. A `.env` file is read first so it can set `WK_STATS_CONFIG`
+    /// as well as any of the overrides. A config file that was asked for explicitly has to exist, while the default
+    /// one is optional.
+    pub fn load() -> Result<Config, ConfigError> {
+        dotenvy::dotenv().ok();
+
+        let (path, required) = match std::env::var("WK_STATS_CONFIG") {
+            Ok(path) => (PathBuf::from(path), true),
+            Err(_) => (PathBuf::from(DEFAULT_CONFIG_PATH), false),
+        };
+
+        let mut config = if required || path.exists() {
+            Config::from_file(&path)?
+        } else {
+            Config::default()
+        };
+
+        config.apply_env(|var| std::env::var(var).ok())?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    fn from_file(path: &Path) -> Result<Config, ConfigError> {
+        let contents =
+            std::fs::read_to_string(path).map_err(|err| ConfigError::Read(path.into(), err))?;
+
+        toml::from_str(&contents).map_err(|err| ConfigError::Parse(path.into(), err))
+    }
+
+    /// Overrides whatever came from the file with the `WK_STATS_*` variables that are set. `lookup` is
+    /// `std::env::var` outside of the tests.
+    fn apply_env(&mut self, lookup: impl Fn(&str) -> Option<String>) -> Result<(), ConfigError> {
+        fn parsed<T: std::str::FromStr>(
+            lookup: &impl Fn(&str) -> Option<String>,
+            var: &'static str,
+            target: &mut T,
+        ) -> Result<(), ConfigError> {
+            if let Some(value) = lookup(var) {
+                *target = value.parse().map_err(|_| ConfigError::Env(var, value))?;
+            }
+
+            Ok(())
+        }
+
+        parsed(&lookup, "WK_STATS_LISTEN_ADDR", &mut self.listen_addr)?;
+        parsed(&lookup, "WK_STATS_BASE_URL", &mut self.wanikani_base_url)?;
+        parsed(
+            &lookup,
+            "WK_STATS_SHUTDOWN_DRAIN_SECS",
+            &mut self.shutdown_drain_secs,
+        )?;
+        parsed(&lookup, "WK_STATS_CACHE_CAPACITY", &mut self.cache.capacity)?;
+        parsed(
+            &lookup,
+            "WK_STATS_CACHE_TTL_SECS",
+            &mut self.cache.user_info_ttl_secs,
+        )?;
+        parsed(
+            &lookup,
+            "WK_STATS_REFRESH_COOLDOWN_SECS",
+            &mut self.cache.refresh_cooldown_secs,
+        )?;
+        parsed(
+            &lookup,
+            "WK_STATS_ACTIVE_USER_IDLE_SECS",
+            &mut self.cache.active_user_idle_secs,
+        )?;
+        parsed(
+            &lookup,
+            "WK_STATS_BACKGROUND_REFRESH_INTERVAL_SECS",
+            &mut self.cache.background_refresh_interval_secs,
+        )?;
+        parsed(
+            &lookup,
+            "WK_STATS_BACKGROUND_REFRESH_MARGIN_SECS",
+            &mut self.cache.background_refresh_margin_secs,
+        )?;
+        parsed(
+            &lookup,
+            "WK_STATS_BACKGROUND_REFRESH_BATCH",
+            &mut self.cache.background_refresh_batch,
+        )?;
+        parsed(
+            &lookup,
+            "WK_STATS_LOAD_FAILURE_TTL_SECS",
+            &mut self.cache.load_failure_ttl_secs,
+        )?;
+        parsed(
+            &lookup,
+            "WK_STATS_RATE_LIMIT_PER_MINUTE",
+            &mut self.rate_limit.requests_per_minute,
+        )?;
+        parsed(
+            &lookup,
+            "WK_STATS_COOKIE_NAME",
+            &mut self.session.cookie_name,
+        )?;
+        parsed(
+            &lookup,
+            "WK_STATS_COOKIE_PATH",
+            &mut self.session.cookie_path,
+        )?;
+        parsed(
+            &lookup,
+            "WK_STATS_COOKIE_SECURE",
+            &mut self.session.cookie_secure,
+        )?;
+        parsed(
+            &lookup,
+            "WK_STATS_COOKIE_SAME_SITE",
+            &mut self.session.cookie_same_site,
+        )?;
+
+        parsed(
+            &lookup,
+            "WK_STATS_SESSION_IDLE_TIMEOUT_SECS",
+            &mut self.session.idle_timeout_secs,
+        )?;
+        parsed(
+            &lookup,
+            "WK_STATS_SESSION_CAPACITY",
+            &mut self.session.capacity,
+        )?;
+        parsed(
+            &lookup,
+            "WK_STATS_CALENDAR_CAPACITY",
+            &mut self.stores.calendar_capacity,
+        )?;
+        parsed(
+            &lookup,
+            "WK_STATS_PROFILE_CAPACITY",
+            &mut self.stores.profile_capacity,
+        )?;
+        parsed(
+            &lookup,
+            "WK_STATS_GROUP_CAPACITY",
+            &mut self.stores.group_capacity,
+        )?;
+
+        if let Some(public_url) = lookup("WK_STATS_PUBLIC_URL") {
+            self.public_url = Some(public_url);
+        }
+
+        if let Some(key) = lookup("WK_STATS_SESSION_KEY") {
+            self.session.key = Some(key);
+        }
+
+        if let Some(domain) = lookup("WK_STATS_COOKIE_DOMAIN") {
+            self.session.cookie_domain = Some(domain);
+        }
+
+        if let Some(value) = lookup("WK_STATS_COOKIE_MAX_AGE_SECS") {
+            let max_age = value
+                .parse()
+                .map_err(|_| ConfigError::Env("WK_STATS_COOKIE_MAX_AGE_SECS", value))?;
+
+            self.session.cookie_max_age_secs = Some(max_age);
+        }
+
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |reason: &str| Err(ConfigError::Invalid(reason.to_string()));
+
+        match reqwest::Url::parse(&self.wanikani_base_url) {
+            Ok(url) if url.scheme() == "http" || url.scheme() == "https" => {}
+            _ => return invalid("wanikani_base_url must be an http or https URL"),
+        }
+
+        if let Some(public_url) = &self.public_url {
+            match reqwest::Url::parse(public_url) {
+                Ok(url) if url.scheme() == "http" || url.scheme() == "https" => {}
+                _ => return invalid("public_url must be an http or https URL"),
+            }
+        }
+
+        if self.cache.capacity == 0 {
+            return invalid("cache.capacity must be at least 1");
+        }
+
+        if self.cache.user_info_ttl_secs == 0 {
+            return invalid("cache.user_info_ttl_secs must be at least 1");
+        }
+
+        if self.cache.background_refresh_interval_secs == 0 {
+            return invalid("cache.background_refresh_interval_secs must be at least 1");
+        }
+
+        // otherwise an entry can expire between two passes of the background refresh
+        if self.cache.background_refresh_margin_secs <= self.cache.background_refresh_interval_secs
+        {
+            return invalid("cache.background_refresh_margin_secs must be longer than cache.background_refresh_interval_secs");
+        }
+
+        if self.cache.background_refresh_batch == 0 {
+            return invalid("cache.background_refresh_batch must be at least 1");
+        }
+
+        if self.rate_limit.requests_per_minute == 0 {
+            return invalid("rate_limit.requests_per_minute must be at least 1");
+        }
+
+        if self.session.capacity == 0 {
+            return invalid("session.capacity must be at least 1");
+        }
+
+        if self.stores.calendar_capacity == 0
+            || self.stores.profile_capacity == 0
+            || self.stores.group_capacity == 0
+        {
+            return invalid("stores.calendar_capacity, stores.profile_capacity and stores.group_capacity must be at least 1");
+        }
+
+        if self.session.idle_timeout_secs == 0 {
+            return invalid("session.idle_timeout_secs must be at least 1");
+        }
+
+        if matches!(&self.session.key, Some(key) if key.len() < 64) {
+            return invalid("session.key must be at least 64 bytes long");
+        }
+
+        if !self.session.cookie_path.starts_with('/') {
+            return invalid("session.cookie_path must start with '/'");
+        }
+
+        if self.session.cookie_name.is_empty()
+            || !self
+                .session
+                .cookie_name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            return invalid("session.cookie_name may only contain letters, digits, '_' and '-'");
+        }
+
+        match self.session.cookie_same_site.to_ascii_lowercase().as_str() {
+            "strict" | "lax" => {}
+            // browsers throw away SameSite=None cookies that aren't also Secure
+            "none" if self.session.cookie_secure => {}
+            "none" => {
+                return invalid(
+                    "session.cookie_same_site = \"none\" requires session.cookie_secure",
+                )
+            }
+            _ => {
+                return invalid("session.cookie_same_site must be \"strict\", \"lax\" or \"none\"")
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn shutdown_drain(&self) -> Duration {
+        Duration::from_secs(self.shutdown_drain_secs)
+    }
+}
+
+impl CacheConfig {
+    pub fn user_info_ttl(&self) -> Duration {
+        Duration::from_secs(self.user_info_ttl_secs)
+    }
+
+    pub fn refresh_cooldown(&self) -> Duration {
+        Duration::from_secs(self.refresh_cooldown_secs)
+    }
+
+    pub fn active_user_idle(&self) -> Duration {
+        Duration::from_secs(self.active_user_idle_secs)
+    }
+
+    pub fn background_refresh_interval(&self) -> Duration {
+        Duration::from_secs(self.background_refresh_interval_secs)
+    }
+
+    pub fn background_refresh_margin(&self) -> Duration {
+        Duration::from_secs(self.background_refresh_margin_secs)
+    }
+
+    pub fn load_failure_ttl(&self) -> Duration {
+        Duration::from_secs(self.load_failure_ttl_secs)
+    }
+}
+
+impl SessionConfig {
+    pub fn idle_timeout(&self) -> Duration {
+        Duration::from_secs(self.idle_timeout_secs)
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_defaults_are_valid() {
+        let config = Config::default();
+
+        assert!(config.validate().is_ok());
+        assert_eq!(config.listen_addr.to_string(), "0.0.0.0:3000");
+        assert_eq!(config.rate_limit.requests_per_minute, 10);
+    }
+
+    #[test]
+    fn test_file_values_fill_in_around_defaults() {
+        let config: Config = toml::from_str(
+            r#"
+            listen_addr = "127.0.0.1:8080"
+
+            [cache]
+            user_info_ttl_secs = 600
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.listen_addr.to_string(), "127.0.0.1:8080");
+        assert_eq!(config.cache.user_info_ttl_secs, 600);
+        assert_eq!(config.cache.capacity, 1000);
+    }
+
+    #[test]
+    fn test_unknown_keys_are_rejected() {
+        let result: Result<Config, _> = toml::from_str("listen_adress = \"127.0.0.1:8080\"");
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_env_overrides_file() {
+        let env: HashMap<&str, &str> = HashMap::from([
+            ("WK_STATS_RATE_LIMIT_PER_MINUTE", "30"),
+            ("WK_STATS_COOKIE_MAX_AGE_SECS", "3600"),
+            ("WK_STATS_SHUTDOWN_DRAIN_SECS", "5"),
+            ("WK_STATS_SESSION_CAPACITY", "50"),
+            ("WK_STATS_BACKGROUND_REFRESH_BATCH", "4"),
+            ("WK_STATS_GROUP_CAPACITY", "20"),
+        ]);
+        let mut config = Config::default();
+
+        config
+            .apply_env(|var| env.get(var).map(|value| value.to_string()))
+            .unwrap();
+
+        assert_eq!(config.rate_limit.requests_per_minute, 30);
+        assert_eq!(config.session.cookie_max_age_secs, Some(3600));
+        assert_eq!(config.shutdown_drain(), Duration::from_secs(5));
+        assert_eq!(config.session.capacity, 50);
+        assert_eq!(config.cache.background_refresh_batch, 4);
+        assert_eq!(config.stores.group_capacity, 20);
+        assert_eq!(config.stores.profile_capacity, 10_000);
+    }
+
+    #[test]
+    fn test_bad_env_value_is_an_error() {
+        let mut config = Config::default();
+
+        let result =
+            config.apply_env(|var| (var == "WK_STATS_CACHE_CAPACITY").then(|| "lots".to_string()));
+
+        assert!(matches!(
+            result,
+            Err(ConfigError::Env("WK_STATS_CACHE_CAPACITY", _))
+        ));
+    }
+
+    #[test]
+    fn test_validation_catches_bad_values() {
+        let mut zero_rate = Config::default();
+        zero_rate.rate_limit.requests_per_minute = 0;
+
+        let mut margin_too_small = Config::default();
+        margin_too_small.cache.background_refresh_margin_secs = 10;
+
+        let mut no_background_refresh = Config::default();
+        no_background_refresh.cache.background_refresh_batch = 0;
+
+        let mut insecure_same_site_none = Config::default();
+        insecure_same_site_none.session.cookie_same_site = "none".to_string();
+
+        let mut short_key = Config::default();
+        short_key.session.key = Some("too short".to_string());
+
+        assert!(zero_rate.validate().is_err());
+        assert!(margin_too_small.validate().is_err());
+        assert!(no_background_refresh.validate().is_err());
+        assert!(insecure_same_site_none.validate().is_err());
+        assert!(short_key.validate().is_err());
+    }
+}
@@ -0,0 +1,333 @@
+use crate::config::SessionConfig;
+use crate::error::AppError;
+use crate::session::site_cookie;
+use axum::{
+    body::{to_bytes, Body},
+    extract::{Request, State},
+    http::{header, Method},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use axum_extra::extract::cookie::{Key, PrivateCookieJar};
+use std::sync::Arc;
+use uuid::Uuid;
+
+const CSRF_COOKIE: &str = "csrf";
+const CSRF_HEADER: &str = "x-csrf-token";
+const CSRF_FIELD: &str = "csrf_token";
+
+/// Forms on this site are tiny, so anything bigger than this isn't one of ours.
+const MAX_FORM_BYTES: usize = 64 * 1024;
+
+/// The token for the current request. Handlers that render a form put it in the template context as
+/// `csrf_token`, and `base.jinja` hands it to htmx so `hx-post` requests send it along as a header.
+#[derive(Clone, Debug)]
+pub struct CsrfToken(pub String);
+
+/// Double-submit CSRF protection for every route. Each browser gets a random token in an encrypted
+/// cookie, and any request that could change something has to send the same token back, either in the
+/// `X-CSRF-Token` header or in a `csrf_token` form field. Another site can make the browser send the
+/// cookie, but it can't read it, so it has no way to know what to put in the form.
+///
+/// Safe methods go straight through, picking up a token if they don't have one yet so the page they
+/// render can include it. The cookie gets the same Secure, SameSite, path and domain attributes as the
+/// session cookie.
+pub async fn verify_csrf(
+    State((key, session)): State<(Key, Arc<SessionConfig>)>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let jar = PrivateCookieJar::from_headers(request.headers(), key);
+    let existing = jar
+        .get(CSRF_COOKIE)
+        .map(|cookie| cookie.value().to_string());
+
+    let mut request = if is_safe(request.method()) {
+        request
+    } else {
+        let Some(expected) = &existing else {
+            return AppError::InvalidCsrfToken.into_response();
+        };
+
+        match check_submitted_token(request, expected).await {
+            Ok(request) => request,
+            Err(err) => return err.into_response(),
+        }
+    };
+
+    let token = existing
+        .clone()
+        .unwrap_or_else(|| Uuid::new_v4().simple().to_string());
+
+    request.extensions_mut().insert(CsrfToken(token.clone()));
+
+    let response = next.run(request).await;
+
+    // a response that shared caches may keep, like a public profile, would hand this token to everyone after
+    if existing.is_some() || is_publicly_cacheable(&response) {
+        return response;
+    }
+
+    (jar.add(site_cookie(&session, CSRF_COOKIE, token)), response).into_response()
+}
+
+fn is_publicly_cacheable(response: &Response) -> bool {
+    response
+        .headers()
+        .get(header::CACHE_CONTROL)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.contains("public"))
+}
+
+fn is_safe(method: &Method) -> bool {
+    matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS)
+}
+
+/// Looks for the token in the header first, then in the form body. Reading the body means buffering it,
+/// so the request is put back together afterwards for the handler's own `Form` extractor.
+async fn check_submitted_token(request: Request, expected: &str) -> Result<Request, AppError> {
+    if let Some(submitted) = request.headers().get(CSRF_HEADER) {
+        return if tokens_match(submitted.as_bytes(), expected.as_bytes()) {
+            Ok(request)
+        } else {
+            Err(AppError::InvalidCsrfToken)
+        };
+    }
+
+    let is_form = request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("application/x-www-form-urlencoded"));
+
+    if !is_form {
+        return Err(AppError::InvalidCsrfToken);
+    }
+
+    let (parts, body) = request.into_parts();
+    let bytes = to_bytes(body, MAX_FORM_BYTES)
+        .await
+        .map_err(|_| AppError::InvalidCsrfToken)?;
+    let submitted = form_urlencoded::parse(&bytes)
+        .find(|(name, _)| name == CSRF_FIELD)
+        .map(|(_, value)| value.into_owned());
+
+    match submitted {
+        Some(submitted) if tokens_match(submitted.as_bytes(), expected.as_bytes()) => {
+            Ok(Request::from_parts(parts, Body::from(bytes)))
+        }
+        _ => Err(AppError::InvalidCsrfToken),
+    }
+}
+
+/// Compares every byte no matter where the first difference is, so the time it takes says nothing about
+/// how much of a guess was right.
+fn tokens_match(submitted: &[u8], expected: &[u8]) -> bool {
+    submitted.len() == expected.len()
+        && submitted
+            .iter()
+            .zip(expected)
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use axum::{http::StatusCode, middleware::from_fn_with_state, routing::post, Form, Router};
+    use axum_extra::extract::cookie::Cookie;
+    use serde::Deserialize;
+    use tower::ServiceExt;
+
+    #[derive(Deserialize)]
+    struct TestForm {
+        name: String,
+    }
+
+    fn app(key: Key) -> Router {
+        Router::new()
+            .route(
+                "/",
+                post(|Form(form): Form<TestForm>| async move { form.name }).get(|| async { "ok" }),
+            )
+            .layer(from_fn_with_state(
+                (key, Arc::new(SessionConfig::default())),
+                verify_csrf,
+            ))
+    }
+
+    /// The `Cookie` header a browser would send back after being given `token`.
+    fn csrf_cookie_header(key: &Key, token: &str) -> String {
+        let response = PrivateCookieJar::new(key.clone())
+            .add(Cookie::new(CSRF_COOKIE, token.to_string()))
+            .into_response();
+        let set_cookie = response.headers()[header::SET_COOKIE].to_str().unwrap();
+
+        set_cookie.split(';').next().unwrap().to_string()
+    }
+
+    fn form_post(cookie: Option<String>, body: &str) -> Request {
+        let mut request =
+            Request::post("/").header(header::CONTENT_TYPE, "application/x-www-form-urlencoded");
+
+        if let Some(cookie) = cookie {
+            request = request.header(header::COOKIE, cookie);
+        }
+
+        request.body(Body::from(body.to_string())).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_get_issues_token_cookie() {
+        let key = Key::generate();
+
+        let response = app(key)
+            .oneshot(Request::get("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers()[header::SET_COOKIE]
+            .to_str()
+            .unwrap()
+            .starts_with("csrf="));
+    }
+
+    #[tokio::test]
+    async fn test_csrf_cookie_attributes() {
+        let session = SessionConfig {
+            cookie_path: "/stats".to_string(),
+            cookie_domain: Some("example.com".to_string()),
+            cookie_secure: true,
+            cookie_same_site: "strict".to_string(),
+            ..SessionConfig::default()
+        };
+        let app = Router::new()
+            .route("/", axum::routing::get(|| async { "ok" }))
+            .layer(from_fn_with_state(
+                (Key::generate(), Arc::new(session)),
+                verify_csrf,
+            ));
+
+        let response = app
+            .oneshot(Request::get("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let set_cookie = response.headers()[header::SET_COOKIE].to_str().unwrap();
+        let cookie = Cookie::parse(set_cookie).unwrap();
+
+        assert_eq!(cookie.name(), CSRF_COOKIE);
+        assert_eq!(cookie.http_only(), Some(true));
+        assert_eq!(cookie.secure(), Some(true));
+        assert_eq!(
+            cookie.same_site(),
+            Some(axum_extra::extract::cookie::SameSite::Strict)
+        );
+        assert_eq!(cookie.path(), Some("/stats"));
+        assert_eq!(cookie.domain(), Some("example.com"));
+        assert_eq!(cookie.max_age(), None);
+    }
+
+    #[tokio::test]
+    async fn test_publicly_cacheable_response_gets_no_cookie() {
+        let app = Router::new()
+            .route(
+                "/",
+                axum::routing::get(|| async {
+                    ([(header::CACHE_CONTROL, "public, max-age=60")], "ok")
+                }),
+            )
+            .layer(from_fn_with_state(
+                (Key::generate(), Arc::new(SessionConfig::default())),
+                verify_csrf,
+            ));
+
+        let response = app
+            .oneshot(Request::get("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert!(response.headers().get(header::SET_COOKIE).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_post_without_cookie_is_rejected() {
+        let key = Key::generate();
+
+        let response = app(key)
+            .oneshot(form_post(None, "name=sarah&csrf_token=anything"))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_post_with_wrong_token_is_rejected() {
+        let key = Key::generate();
+        let cookie = csrf_cookie_header(&key, "expected");
+
+        let response = app(key)
+            .oneshot(form_post(Some(cookie), "name=sarah&csrf_token=forged"))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_post_without_token_is_rejected() {
+        let key = Key::generate();
+        let cookie = csrf_cookie_header(&key, "expected");
+
+        let response = app(key)
+            .oneshot(form_post(Some(cookie), "name=sarah"))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_cookie_from_another_key_is_rejected() {
+        let cookie = csrf_cookie_header(&Key::generate(), "expected");
+
+        let response = app(Key::generate())
+            .oneshot(form_post(Some(cookie), "name=sarah&csrf_token=expected"))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_matching_form_field_is_accepted_and_body_survives() {
+        let key = Key::generate();
+        let cookie = csrf_cookie_header(&key, "expected");
+
+        let response = app(key)
+            .oneshot(form_post(Some(cookie), "name=sarah&csrf_token=expected"))
+            .await
+            .unwrap();
+        let body = to_bytes(response.into_body(), MAX_FORM_BYTES)
+            .await
+            .unwrap();
+
+        assert_eq!(&body[..], b"sarah");
+    }
+
+    #[tokio::test]
+    async fn test_matching_header_is_accepted() {
+        let key = Key::generate();
+        let cookie = csrf_cookie_header(&key, "expected");
+        let mut request = form_post(Some(cookie), "name=sarah");
+        request
+            .headers_mut()
+            .insert(CSRF_HEADER, "expected".parse().unwrap());
+
+        let response = app(key).oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
@@ -1,5 +1,9 @@
+pub mod anki;
 pub mod api_client;
 pub mod complete_user_info;
+pub mod query;
+pub mod report;
+pub mod snapshot;
 
 use chrono::{DateTime, Local};
 use governor::DefaultDirectRateLimiter;
@@ -8,6 +12,12 @@ use std::collections::HashMap;
 use std::error;
 use std::fmt;
 use std::marker::PhantomData;
+use std::sync::Mutex;
+use std::time::Instant;
+use tokio::sync::watch;
+
+/// Where the WaniKani API lives unless an `ApiClient` is told otherwise with `with_base_url`.
+pub const DEFAULT_BASE_URL: &str = "https://api.wanikani.com/v2";
 
 #[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
 struct User {
@@ -45,22 +55,84 @@ struct Meanings {
     primary: bool,
 }
 
+#[derive(Deserialize, Serialize, Debug, Clone)]
+struct Readings {
+    reading: String,
+    primary: bool,
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone)]
 struct Subject {
     characters: Option<String>,
     level: i32,
     spaced_repetition_system_id: i32,
     meanings: Vec<Meanings>,
+    /// Radicals and kana vocabulary have nothing to read, and WaniKani leaves the field out for them.
+    #[serde(default)]
+    readings: Vec<Readings>,
+}
+
+impl Subject {
+    /// What to call the subject when showing it to someone. Some radicals are only an image
+    /// and have no characters, so their primary meaning stands in for them.
+    fn display_characters(&self) -> String {
+        match &self.characters {
+            Some(characters) => characters.clone(),
+            None => self.primary_meaning().unwrap_or_default().to_string(),
+        }
+    }
+
+    fn primary_meaning(&self) -> Option<&str> {
+        self.meanings
+            .iter()
+            .find(|meaning| meaning.primary)
+            .and_then(|meaning| meaning.meaning.as_deref())
+    }
+
+    /// Every meaning WaniKani accepts, primary one first.
+    fn meanings(&self) -> Vec<&str> {
+        let mut meanings: Vec<&Meanings> = self.meanings.iter().collect();
+        meanings.sort_by_key(|meaning| !meaning.primary);
+
+        meanings
+            .into_iter()
+            .filter_map(|meaning| meaning.meaning.as_deref())
+            .collect()
+    }
+
+    /// Every reading, primary ones first.
+    fn readings(&self) -> Vec<&str> {
+        let mut readings: Vec<&Readings> = self.readings.iter().collect();
+        readings.sort_by_key(|reading| !reading.primary);
+
+        readings
+            .into_iter()
+            .map(|reading| reading.reading.as_str())
+            .collect()
+    }
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
 struct Assignment {
     created_at: Option<DateTime<Local>>,
     passed_at: Option<DateTime<Local>>,
+    /// When the next review is due. WaniKani leaves this empty for subjects still in lessons
+    /// and for burned ones.
+    available_at: Option<DateTime<Local>>,
     srs_stage: i32,
     subject_id: i32,
 }
 
+/// One level the user has been on. A reset abandons the levels it takes the user back past, and the
+/// level they are on now has not been passed yet.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+struct LevelProgression {
+    level: i32,
+    unlocked_at: Option<DateTime<Local>>,
+    passed_at: Option<DateTime<Local>>,
+    abandoned_at: Option<DateTime<Local>>,
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone)]
 struct Reset {
     created_at: DateTime<Local>,
@@ -92,11 +164,85 @@ struct ReqwestResponse<T> {
 /// You really just need to create an instance of this struct and then call the
 /// `build_complete_user_info` method. This will return a `CompleteUserInfo` struct that will
 /// contain all the information that you need.
-#[derive(Debug)]
 pub struct ApiClient<'a> {
     token: String,
     client: &'a reqwest::Client,
     limiter: &'a DefaultDirectRateLimiter,
+    base_url: &'a str,
+    progress: Option<&'a watch::Sender<FetchProgress>>,
+    contact: Option<&'a ApiContact>,
+}
+
+/// Written by hand so the token can never end up in a log line through `{:?}`.
+impl fmt::Debug for ApiClient<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ApiClient")
+            .field("token", &"[redacted]")
+            .field("base_url", &self.base_url)
+            .finish_non_exhaustive()
+    }
+}
+
+/// When we last got an answer out of WaniKani, shared by every `ApiClient` that is given it with
+/// `with_contact`. Any response counts except a server error, since even a rejected token means
+/// WaniKani is up and talking to us.
+#[derive(Debug, Default)]
+pub struct ApiContact {
+    last_response: Mutex<Option<Instant>>,
+}
+
+impl ApiContact {
+    pub fn record_response(&self) {
+        *self.last_response.lock().unwrap() = Some(Instant::now());
+    }
+
+    pub fn last_response(&self) -> Option<Instant> {
+        *self.last_response.lock().unwrap()
+    }
+}
+
+/// How far along a paged collection is. `total` is worked out from the first page, so it is
+/// zero until that page has arrived.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct PageProgress {
+    pub fetched: i32,
+    pub total: i32,
+}
+
+/// A running tally of the pages an `ApiClient` has fetched for the slow, paged parts of
+/// `build_complete_user_info`. Hand the client a `watch::Sender` with `with_progress` and it
+/// will update this after every page.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct FetchProgress {
+    pub review_stats: PageProgress,
+    pub assignments: PageProgress,
+    pub subjects: PageProgress,
+}
+
+impl fmt::Display for FetchProgress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let collections = [
+            ("review statistics", self.review_stats),
+            ("assignments", self.assignments),
+            ("subjects", self.subjects),
+        ];
+        let fetched: Vec<String> = collections
+            .iter()
+            .filter(|(_, progress)| progress.total > 0)
+            .map(|(name, progress)| {
+                format!(
+                    "{} of {} pages of {}",
+                    progress.fetched, progress.total, name
+                )
+            })
+            .collect();
+
+        if fetched.is_empty() {
+            write!(f, "Asking WaniKani for your data")
+        } else {
+            write!(f, "Fetched {}", fetched.join(", "))
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -105,7 +251,9 @@ struct CompleteUserInfoBuilder {
     review_stats: Vec<ReviewStatistic>,
     assignments: Vec<Assignment>,
     resets: Vec<Reset>,
+    level_progressions: Vec<LevelProgression>,
     id_to_subjects: HashMap<i32, SubjectWithType>,
+    fetched_at: DateTime<Local>,
 }
 
 /// This is the most important struct in the project. As the entire purpose of this
@@ -123,25 +271,49 @@ pub struct CompleteUserInfo {
     review_stats: Vec<ReviewStatistic>,
     assignments: Vec<Assignment>,
     resets: Vec<Reset>,
+    level_progressions: Vec<LevelProgression>,
     id_to_subjects: HashMap<i32, SubjectWithType>,
+    fetched_at: DateTime<Local>,
     kanji_learned: i32,
     radicals_learned: i32,
     vocab_learned: i32,
     kana_learned: i32,
-    kanji_stats: SubjectTypeStats,
-    radical_stats: SubjectTypeStats,
-    vocab_stats: SubjectTypeStats,
-    kana_stats: SubjectTypeStats,
 }
 
-#[derive(Debug, PartialEq, Clone)]
-enum SubjectType {
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SubjectType {
     KanaVocabulary,
     Kanji,
     Radical,
     Vocabulary,
 }
 
+impl SubjectType {
+    pub const ALL: [SubjectType; 4] = [
+        SubjectType::Radical,
+        SubjectType::Kanji,
+        SubjectType::Vocabulary,
+        SubjectType::KanaVocabulary,
+    ];
+
+    /// The type WaniKani calls `name`, the other way around from `as_str`.
+    pub fn from_name(name: &str) -> Option<SubjectType> {
+        SubjectType::ALL
+            .into_iter()
+            .find(|subject_type| subject_type.as_str() == name)
+    }
+
+    /// The name WaniKani uses for this type in its `object` fields.
+    fn as_str(&self) -> &'static str {
+        match self {
+            SubjectType::KanaVocabulary => "kana_vocabulary",
+            SubjectType::Kanji => "kanji",
+            SubjectType::Radical => "radical",
+            SubjectType::Vocabulary => "vocabulary",
+        }
+    }
+}
+
 /// This is a custom error type that would really only occur if the API response
 /// was not what was expected. This is a very unlikely error to occur and if it
 /// does, then that would be on WaniKani's end.
@@ -156,18 +328,131 @@ impl fmt::Display for MissingSubjectError {
 
 impl error::Error for MissingSubjectError {}
 
-/// This is one of the only structs that has every field public. This is because
-/// the fields are basically exactly what goes into the templates. This is reliant
-/// on the enum `SubjectType` to determine what type of subject it is. So there will
-/// be four of these structs in the `CompleteUserInfo` struct.
-#[derive(Debug, Clone)]
-#[allow(unused)]
-struct SubjectTypeStats {
-    pub subject_type: SubjectType,
-    pub num_of_meaning_correct: i32,
-    pub num_of_meaning_incorrect: i32,
-    pub num_of_reading_correct: i32,
-    pub num_of_reading_incorrect: i32,
+/// The ways a conversation with WaniKani can go wrong that are worth telling the
+/// user about. A bad token, a token that is not allowed to read one of the endpoints
+/// we need, being rate limited, and WaniKani simply not answering all call for
+/// different advice, so they get their own variants instead of a bare `reqwest::Error`.
+/// `UnexpectedResponse` covers WaniKani answering with something we couldn't make sense
+/// of, including the `MissingSubjectError` case.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WaniKaniError {
+    InvalidToken,
+    MissingReadAccess(&'static str),
+    RateLimited,
+    Unavailable,
+    UnexpectedResponse,
+}
+
+impl WaniKaniError {
+    /// Maps the status code of a failed request onto the error the user should see.
+    /// A missing status code means we never got a response at all.
+    pub fn from_status(status: Option<reqwest::StatusCode>) -> Self {
+        match status {
+            Some(reqwest::StatusCode::UNAUTHORIZED) => WaniKaniError::InvalidToken,
+            Some(reqwest::StatusCode::FORBIDDEN) => WaniKaniError::MissingReadAccess("account"),
+            Some(reqwest::StatusCode::TOO_MANY_REQUESTS) => WaniKaniError::RateLimited,
+            _ => WaniKaniError::Unavailable,
+        }
+    }
+}
+
+impl From<reqwest::Error> for WaniKaniError {
+    fn from(err: reqwest::Error) -> Self {
+        if err.is_decode() {
+            return WaniKaniError::UnexpectedResponse;
+        }
+
+        WaniKaniError::from_status(err.status())
+    }
+}
+
+impl From<MissingSubjectError> for WaniKaniError {
+    fn from(_: MissingSubjectError) -> Self {
+        WaniKaniError::UnexpectedResponse
+    }
+}
+
+impl fmt::Display for WaniKaniError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WaniKaniError::InvalidToken => {
+                write!(f, "WaniKani did not recognize that API token")
+            }
+            WaniKaniError::MissingReadAccess(endpoint) => {
+                write!(f, "That API token is not allowed to read your {}", endpoint)
+            }
+            WaniKaniError::RateLimited => {
+                write!(
+                    f,
+                    "WaniKani is rate limiting us right now, try again in a minute"
+                )
+            }
+            WaniKaniError::Unavailable => {
+                write!(f, "WaniKani could not be reached, try again shortly")
+            }
+            WaniKaniError::UnexpectedResponse => {
+                write!(f, "WaniKani sent back data we did not understand")
+            }
+        }
+    }
+}
+
+impl error::Error for WaniKaniError {}
+
+/// The two halves of a review. Radicals and kana vocabulary only ever get asked their meaning.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Question {
+    Meaning,
+    Reading,
+}
+
+/// How many answers were right and how many were wrong. Accuracies used to be plain percentages,
+/// which came out as NaN for anyone with nothing reviewed yet (new users, a type they haven't reached,
+/// everything right after a reset), so now the counts are kept and `percentage` says when there is
+/// nothing to work a percentage out from.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, Serialize)]
+pub struct Accuracy {
+    pub correct: i32,
+    pub incorrect: i32,
+}
+
+impl Accuracy {
+    pub fn total(&self) -> i32 {
+        self.correct + self.incorrect
+    }
+
+    /// Empty when there haven't been any answers.
+    pub fn percentage(&self) -> Option<f64> {
+        (self.total() > 0).then(|| self.correct as f64 / self.total() as f64 * 100.0)
+    }
+}
+
+impl std::ops::Add for Accuracy {
+    type Output = Accuracy;
+
+    fn add(self, other: Accuracy) -> Accuracy {
+        Accuracy {
+            correct: self.correct + other.correct,
+            incorrect: self.incorrect + other.incorrect,
+        }
+    }
+}
+
+impl std::iter::Sum for Accuracy {
+    fn sum<I: Iterator<Item = Accuracy>>(iter: I) -> Accuracy {
+        iter.fold(Accuracy::default(), |sum, accuracy| sum + accuracy)
+    }
+}
+
+/// The percentage the way the info page shows it, or `--` without any answers.
+impl fmt::Display for Accuracy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.percentage() {
+            Some(percentage) => write!(f, "{:.2}%", percentage),
+            None => write!(f, "--"),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -0,0 +1,213 @@
+use super::report::leeches;
+use super::*;
+use std::collections::HashSet;
+use std::io::Write;
+
+/// Which subjects go into a deck. Anything left unset lets every subject through, so the default
+/// filter is everything the user has been assigned.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct DeckFilter {
+    /// Only keep subjects that `report::leeches` lists.
+    #[serde(default)]
+    pub leeches: bool,
+    pub min_level: Option<i32>,
+    pub max_level: Option<i32>,
+    /// WaniKani's names for the subject types to keep, separated by commas, like `kanji,vocabulary`.
+    pub types: Option<String>,
+}
+
+impl DeckFilter {
+    fn allows_level(&self, level: i32) -> bool {
+        self.min_level.is_none_or(|min_level| level >= min_level)
+            && self.max_level.is_none_or(|max_level| level <= max_level)
+    }
+
+    fn allows_type(&self, subject_type: &SubjectType) -> bool {
+        match &self.types {
+            Some(types) => types
+                .split(',')
+                .any(|name| name.trim() == subject_type.as_str()),
+            None => true,
+        }
+    }
+}
+
+/// One note in the deck. The field order is the column order of the file.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AnkiNote {
+    pub characters: String,
+    /// Every accepted meaning, primary first, separated by commas.
+    pub meanings: String,
+    /// Every reading, primary first, separated by commas. Empty for radicals and kana vocabulary.
+    pub readings: String,
+    pub subject_type: &'static str,
+    pub level: i32,
+    /// Separated by spaces, which is how Anki reads a tags column.
+    pub tags: String,
+}
+
+/// The subjects the filter lets through, by level and then by id. Every note is tagged with where it
+/// came from so the whole lot can be found (or deleted) again in Anki, and leeches get a tag of their own.
+pub fn anki_notes(info: &CompleteUserInfo, filter: &DeckFilter) -> Vec<AnkiNote> {
+    let leech_ids: HashSet<i32> = leeches(info)
+        .into_iter()
+        .map(|leech| leech.subject_id)
+        .collect();
+
+    let mut assignments: Vec<(&Assignment, &SubjectWithType)> = info
+        .assignments
+        .iter()
+        .filter_map(|assignment| {
+            let subject = info.id_to_subjects.get(&assignment.subject_id)?;
+
+            Some((assignment, subject))
+        })
+        .filter(|(assignment, subject)| {
+            filter.allows_level(subject.subject.level)
+                && filter.allows_type(&subject.subject_type)
+                && (!filter.leeches || leech_ids.contains(&assignment.subject_id))
+        })
+        .collect();
+
+    assignments.sort_by_key(|(assignment, subject)| (subject.subject.level, assignment.subject_id));
+
+    assignments
+        .into_iter()
+        .map(|(assignment, subject)| {
+            let subject_type = subject.subject_type.as_str();
+            let mut tags = format!(
+                "wanikani wanikani::{} wanikani::level{:02}",
+                subject_type, subject.subject.level
+            );
+
+            if leech_ids.contains(&assignment.subject_id) {
+                tags.push_str(" wanikani::leech");
+            }
+
+            AnkiNote {
+                characters: subject.subject.display_characters(),
+                meanings: subject.subject.meanings().join(", "),
+                readings: subject.subject.readings().join(", "),
+                subject_type,
+                level: subject.subject.level,
+                tags,
+            }
+        })
+        .collect()
+}
+
+/// Writes the notes as a text file Anki can bring in with File > Import. The lines at the top tell Anki
+/// the file is tab separated plain text, what each column is and which one holds the tags, so the import
+/// dialog comes up already filled in and all that is left is picking a note type and deck.
+pub fn write_tsv<W: Write>(notes: &[AnkiNote], mut writer: W) -> Result<(), csv::Error> {
+    writeln!(writer, "#separator:Tab")?;
+    writeln!(writer, "#html:false")?;
+    writeln!(
+        writer,
+        "#columns:Characters\tMeanings\tReadings\tType\tLevel\tTags"
+    )?;
+    writeln!(writer, "#tags column:6")?;
+
+    let mut tsv = csv::WriterBuilder::new()
+        .delimiter(b'\t')
+        .has_headers(false)
+        .from_writer(writer);
+
+    for note in notes {
+        tsv.serialize(note)?;
+    }
+
+    tsv.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::data_processing::complete_user_info::test::{
+        fake_assignment, fake_review_statistic, fake_user_info,
+    };
+
+    fn user_info() -> CompleteUserInfo {
+        let leech = ReviewStatistic {
+            meaning_incorrect: 12,
+            meaning_current_streak: 1,
+            ..fake_review_statistic(2, "vocabulary")
+        };
+
+        fake_user_info(
+            10,
+            &[(1, SubjectType::Kanji, 5), (2, SubjectType::Vocabulary, 8)],
+            vec![fake_review_statistic(1, "kanji"), leech],
+            vec![fake_assignment(1), fake_assignment(2)],
+        )
+    }
+
+    #[test]
+    fn test_notes_carry_every_meaning_and_reading() {
+        let notes = anki_notes(&user_info(), &DeckFilter::default());
+
+        assert_eq!(notes.len(), 2);
+        assert_eq!(notes[0].characters, "日");
+        assert_eq!(notes[0].meanings, "one, first");
+        assert_eq!(notes[0].readings, "にち, ひ");
+        assert_eq!(notes[0].tags, "wanikani wanikani::kanji wanikani::level05");
+        assert_eq!(notes[1].subject_type, "vocabulary");
+        assert!(notes[1].tags.ends_with("wanikani::leech"));
+    }
+
+    #[test]
+    fn test_filters_narrow_the_deck() {
+        let info = user_info();
+        let only = |filter: DeckFilter| -> Vec<String> {
+            anki_notes(&info, &filter)
+                .into_iter()
+                .map(|note| note.characters)
+                .collect()
+        };
+
+        assert_eq!(
+            only(DeckFilter {
+                leeches: true,
+                ..Default::default()
+            }),
+            ["食べる"]
+        );
+        assert_eq!(
+            only(DeckFilter {
+                min_level: Some(6),
+                max_level: Some(10),
+                ..Default::default()
+            }),
+            ["食べる"]
+        );
+        assert_eq!(
+            only(DeckFilter {
+                types: Some("kanji, radical".to_string()),
+                ..Default::default()
+            }),
+            ["日"]
+        );
+    }
+
+    #[test]
+    fn test_tsv_starts_with_import_headers() {
+        let notes = anki_notes(&user_info(), &DeckFilter::default());
+        let mut written = Vec::new();
+
+        write_tsv(&notes, &mut written).unwrap();
+
+        let written = String::from_utf8(written).unwrap();
+        let lines: Vec<&str> = written.lines().collect();
+
+        assert_eq!(lines[0], "#separator:Tab");
+        assert_eq!(lines[3], "#tags column:6");
+        assert_eq!(
+            lines[4],
+            "日\tone, first\tにち, ひ\tkanji\t5\twanikani wanikani::kanji wanikani::level05"
+        );
+        assert_eq!(lines.len(), 6);
+    }
+}
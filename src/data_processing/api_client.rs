@@ -4,12 +4,24 @@ use serde::de::DeserializeOwned;
 use serde::Serialize;
 use std::collections::{HashMap, HashSet};
 use std::marker::PhantomData;
-
-const USER_URL: &str = "https://api.wanikani.com/v2/user";
-const RESETS_URL: &str = "https://api.wanikani.com/v2/resets";
-const REVIEW_STATS_URL: &str = "https://api.wanikani.com/v2/review_statistics";
-const SUBJECT_URL: &str = "https://api.wanikani.com/v2/subjects";
-const ASSIGNMENT_URL: &str = "https://api.wanikani.com/v2/assignments";
+use std::time::Instant;
+use tracing::{field, Instrument};
+
+const USER_PATH: &str = "/user";
+const RESETS_PATH: &str = "/resets";
+const REVIEW_STATS_PATH: &str = "/review_statistics";
+const SUBJECT_PATH: &str = "/subjects";
+const ASSIGNMENT_PATH: &str = "/assignments";
+const LEVEL_PROGRESSIONS_PATH: &str = "/level_progressions";
+
+/// Every user specific collection that `build_complete_user_info` reads, paired with
+/// the name we show the user if their token can't read it.
+const REQUIRED_READ_PATHS: [(&str, &str); 4] = [
+    (REVIEW_STATS_PATH, "review statistics"),
+    (ASSIGNMENT_PATH, "assignments"),
+    (RESETS_PATH, "resets"),
+    (LEVEL_PROGRESSIONS_PATH, "level progressions"),
+];
 
 type ApiClientError = reqwest::Error;
 
@@ -31,9 +43,102 @@ impl<'a> ApiClient<'a> {
             token,
             client,
             limiter,
+            base_url: DEFAULT_BASE_URL,
+            progress: None,
+            contact: None,
         }
     }
 
+    /// Points the client at a different copy of the API, such as a proxy or a mock server.
+    /// `base_url` is everything before the endpoint paths, like `DEFAULT_BASE_URL`.
+    pub fn with_base_url(mut self, base_url: &'a str) -> Self {
+        self.base_url = base_url.trim_end_matches('/');
+        self
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    /// Has the client report every page it fetches for review statistics, assignments and
+    /// subjects to `progress`, so whoever is waiting on a large account can be told how far
+    /// along it is.
+    pub fn with_progress(mut self, progress: &'a watch::Sender<FetchProgress>) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    /// The endpoint a url belongs to, for labelling metrics. Anything we don't recognise is lumped
+    /// together so a strange url can't create a new time series.
+    fn endpoint(&self, url: &str) -> &'static str {
+        let path = url.strip_prefix(self.base_url).unwrap_or(url);
+
+        [
+            USER_PATH,
+            RESETS_PATH,
+            REVIEW_STATS_PATH,
+            SUBJECT_PATH,
+            ASSIGNMENT_PATH,
+            LEVEL_PROGRESSIONS_PATH,
+        ]
+        .into_iter()
+        .find(|endpoint| path.starts_with(endpoint))
+        .unwrap_or("other")
+    }
+
+    /// The url without the base or the query string, which is all that is worth putting in a log.
+    fn path<'u>(&self, url: &'u str) -> &'u str {
+        let path = url.strip_prefix(self.base_url).unwrap_or(url);
+
+        path.split('?').next().unwrap_or(path)
+    }
+
+    /// Waits for the rate limiter and records how long that took, since a slow /info page is just as
+    /// likely to be us queueing behind other users as it is WaniKani being slow.
+    async fn wait_for_rate_limiter(&self) {
+        let started = Instant::now();
+
+        self.limiter.until_ready().await;
+
+        metrics::histogram!("wanikani_rate_limiter_wait_seconds")
+            .record(started.elapsed().as_secs_f64());
+    }
+
+    /// Has the client note down every time WaniKani answers it, so the server can tell whether
+    /// WaniKani is reachable without asking it again.
+    pub fn with_contact(mut self, contact: &'a ApiContact) -> Self {
+        self.contact = Some(contact);
+        self
+    }
+
+    fn record_page<T>(&self, url: &str, page: &PagedData<T>) {
+        let Some(progress) = self.progress else {
+            return;
+        };
+
+        let path = url.strip_prefix(self.base_url).unwrap_or(url);
+
+        progress.send_modify(|progress| {
+            let collection = if path.starts_with(REVIEW_STATS_PATH) {
+                &mut progress.review_stats
+            } else if path.starts_with(ASSIGNMENT_PATH) {
+                &mut progress.assignments
+            } else if path.starts_with(SUBJECT_PATH) {
+                &mut progress.subjects
+            } else {
+                return;
+            };
+
+            if let Some(PageData { per_page, .. }) = page.pages {
+                if per_page > 0 {
+                    collection.total = (page.total_count + per_page - 1) / per_page;
+                }
+            }
+
+            collection.fetched += 1;
+        });
+    }
+
     async fn get_response<T>(&self, url: &str) -> Result<ReqwestResponse<T>, ApiClientError>
     where
         T: DeserializeOwned,
@@ -45,6 +150,20 @@ impl<'a> ApiClient<'a> {
         Ok(result)
     }
 
+    /// Every call to WaniKani goes through here and gets its own `wanikani_request` span. A call is never retried, since
+    /// the rate limiter and the user info cache already keep us from hammering WaniKani, so `retries` is always 0; it is
+    /// still recorded so log queries can count on the field being there.
+    #[tracing::instrument(
+        name = "wanikani_request",
+        skip_all,
+        fields(
+            endpoint = self.endpoint(url),
+            path = self.path(url),
+            retries = 0,
+            status = field::Empty,
+            duration_ms = field::Empty,
+        )
+    )]
     async fn get_response_with_params<T, K>(
         &self,
         url: &str,
@@ -54,19 +173,48 @@ impl<'a> ApiClient<'a> {
         T: DeserializeOwned,
         K: Serialize,
     {
-        let mut response = self
+        let started = Instant::now();
+        let mut request = self
             .client
             .get(url)
             .header("Authorization", format!("Bearer {}", self.token));
 
-        if let Some(valid_param) = params {
-            response = response.query(&valid_param)
+        if let Some(valid_param) = &params {
+            request = request.query(valid_param)
         }
 
-        let response = response.send().await?;
+        let response = request.send().await;
+        let status = match &response {
+            Ok(response) => response.status().as_u16().to_string(),
+            Err(_) => "error".to_string(),
+        };
+
+        metrics::counter!(
+            "wanikani_api_requests_total",
+            "endpoint" => self.endpoint(url),
+            "status" => status
+        )
+        .increment(1);
+
+        let span = tracing::Span::current();
+        span.record("duration_ms", started.elapsed().as_millis() as u64);
+
+        match &response {
+            Ok(response) => {
+                span.record("status", response.status().as_u16());
+
+                if let (Some(contact), false) = (self.contact, response.status().is_server_error())
+                {
+                    contact.record_response();
+                }
+
+                tracing::info!("WaniKani request finished");
+            }
+            Err(err) => tracing::warn!(error = %err, "WaniKani request failed"),
+        }
 
         Ok(ReqwestResponse::<T> {
-            raw_response: response.error_for_status()?,
+            raw_response: response?.error_for_status()?,
             resource_type: PhantomData,
         })
     }
@@ -84,12 +232,44 @@ impl<'a> ApiClient<'a> {
     }
 
     async fn get_user_data(&self) -> Result<User, ApiClientError> {
-        let raw = self.get_response::<Response<User>>(USER_URL).await?;
+        self.wait_for_rate_limiter().await;
+
+        let raw = self
+            .get_response::<Response<User>>(&self.url(USER_PATH))
+            .await?;
         let processed = self.raw_response_to_data(raw).await?;
 
         Ok(processed.data)
     }
 
+    /// Checks that the token belongs to a WaniKani account and that it can read every
+    /// collection we need before we hand out a session for it. The collection checks ask
+    /// only for records updated after right now, so WaniKani answers each with an empty
+    /// page instead of the user's whole history. Each of these counts against WaniKani's rate
+    /// limit just like a full fetch does, so they all wait their turn behind the limiter.
+    #[tracing::instrument(skip_all)]
+    pub async fn validate_token(&self) -> Result<(), WaniKaniError> {
+        self.get_user_data().await?;
+
+        let now = chrono::Utc::now().to_rfc3339();
+
+        for (path, name) in REQUIRED_READ_PATHS {
+            let params = vec![("updated_after", now.as_str())];
+
+            self.wait_for_rate_limiter().await;
+            self.get_response_with_params::<serde_json::Value, &str>(&self.url(path), Some(params))
+                .await
+                .map_err(|err| match WaniKaniError::from(err) {
+                    WaniKaniError::InvalidToken | WaniKaniError::MissingReadAccess(_) => {
+                        WaniKaniError::MissingReadAccess(name)
+                    }
+                    other => other,
+                })?;
+        }
+
+        Ok(())
+    }
+
     async fn get_all_pages_of_paged_data<T>(
         &self,
         paged_url: &str,
@@ -113,16 +293,23 @@ impl<'a> ApiClient<'a> {
         T: DeserializeOwned,
         K: Serialize,
     {
-        self.limiter.until_ready().await;
+        self.wait_for_rate_limiter().await;
 
+        let mut page = 1;
         let raw = match params {
             Some(_) => {
                 self.get_response_with_params::<PagedData<T>, K>(paged_url, params)
+                    .instrument(tracing::info_span!("wanikani_page", page))
+                    .await?
+            }
+            None => {
+                self.get_response::<PagedData<T>>(paged_url)
+                    .instrument(tracing::info_span!("wanikani_page", page))
                     .await?
             }
-            None => self.get_response::<PagedData<T>>(paged_url).await?,
         };
         let mut processed = self.raw_response_to_data(raw).await?;
+        self.record_page(paged_url, &processed);
         let mut result: Vec<Response<T>> = processed.data;
 
         while let Some(PageData {
@@ -130,9 +317,14 @@ impl<'a> ApiClient<'a> {
             ..
         }) = processed.pages
         {
-            self.limiter.until_ready().await;
-            let raw = self.get_response::<PagedData<T>>(url).await?;
+            self.wait_for_rate_limiter().await;
+            page += 1;
+            let raw = self
+                .get_response::<PagedData<T>>(url)
+                .instrument(tracing::info_span!("wanikani_page", page))
+                .await?;
             processed = self.raw_response_to_data(raw).await?;
+            self.record_page(paged_url, &processed);
 
             result.append(&mut processed.data);
         }
@@ -168,22 +360,34 @@ impl<'a> ApiClient<'a> {
             .collect();
         let query_params = vec![("ids", subject_list_strs.join(","))];
         let all_subjects: Vec<Response<Subject>> = self
-            .get_all_pages_of_paged_data_with_params(SUBJECT_URL, Some(query_params))
+            .get_all_pages_of_paged_data_with_params(&self.url(SUBJECT_PATH), Some(query_params))
             .await?;
+        // A subject of a type we don't know or without an id is skipped rather than panicking, which would
+        // take down whichever cache load was running. If a review statistic or assignment points at one,
+        // building the `CompleteUserInfo` fails with a `MissingSubjectError` and the user gets an error.
         let result: HashMap<i32, SubjectWithType> = all_subjects
             .into_iter()
-            .map(|response| {
+            .filter_map(|response| {
                 let subject_type = match response.object.as_str() {
                     "radical" => SubjectType::Radical,
                     "kanji" => SubjectType::Kanji,
                     "vocabulary" => SubjectType::Vocabulary,
                     "kana_vocabulary" => SubjectType::KanaVocabulary,
-                    _ => panic!("Unknown Subject Type"),
+                    other => {
+                        tracing::warn!(
+                            object = other,
+                            id = response.id,
+                            "skipping subject of unknown type"
+                        );
+                        return None;
+                    }
                 };
-                (
-                    response.id.unwrap(),
-                    SubjectWithType::new(response.data, subject_type),
-                )
+                let Some(id) = response.id else {
+                    tracing::warn!("skipping subject without an id");
+                    return None;
+                };
+
+                Some((id, SubjectWithType::new(response.data, subject_type)))
             })
             .collect();
 
@@ -191,15 +395,25 @@ impl<'a> ApiClient<'a> {
     }
 
     async fn get_all_assignments(&self) -> Result<Vec<Response<Assignment>>, ApiClientError> {
-        self.get_all_pages_of_paged_data(ASSIGNMENT_URL).await
+        self.get_all_pages_of_paged_data(&self.url(ASSIGNMENT_PATH))
+            .await
     }
 
     async fn get_all_resets(&self) -> Result<Vec<Response<Reset>>, ApiClientError> {
-        self.get_all_pages_of_paged_data(RESETS_URL).await
+        self.get_all_pages_of_paged_data(&self.url(RESETS_PATH))
+            .await
+    }
+
+    async fn get_all_level_progressions(
+        &self,
+    ) -> Result<Vec<Response<LevelProgression>>, ApiClientError> {
+        self.get_all_pages_of_paged_data(&self.url(LEVEL_PROGRESSIONS_PATH))
+            .await
     }
 
     async fn get_all_review_stats(&self) -> Result<Vec<Response<ReviewStatistic>>, ApiClientError> {
-        self.get_all_pages_of_paged_data(REVIEW_STATS_URL).await
+        self.get_all_pages_of_paged_data(&self.url(REVIEW_STATS_PATH))
+            .await
     }
 
     /// This is one of the few methods that actually needs to be called outside of the data_processing
@@ -207,14 +421,15 @@ impl<'a> ApiClient<'a> {
     /// output all the aggregated data that was gathered from the API.
     ///
     /// This accepts no arguments and returns a `Result` that contains either a `CompleteUserInfo`
-    /// struct or a `Box<dyn std::error::Error>`.
-    pub async fn build_complete_user_info(
-        &self,
-    ) -> Result<CompleteUserInfo, Box<dyn std::error::Error>> {
+    /// struct or a `WaniKaniError` saying what went wrong.
+    #[tracing::instrument(skip_all)]
+    pub async fn build_complete_user_info(&self) -> Result<CompleteUserInfo, WaniKaniError> {
+        let fetched_at = Local::now();
         let user_data = self.get_user_data().await?;
         let review_data = self.get_all_review_stats().await?;
         let assignment_data = self.get_all_assignments().await?;
         let reset_data = self.get_all_resets().await?;
+        let level_progression_data = self.get_all_level_progressions().await?;
         let sub_vec = self.get_list_of_subjects_to_request(&review_data, &assignment_data);
         let hashy = self.construct_id_to_subject_hash(&sub_vec).await?;
 
@@ -233,10 +448,111 @@ impl<'a> ApiClient<'a> {
                 .map(|response| response.data)
                 .collect(),
             hashy,
-        );
+        )
+        .with_level_progressions(
+            level_progression_data
+                .into_iter()
+                .map(|response| response.data)
+                .collect(),
+        )
+        .with_fetched_at(fetched_at);
 
         Ok(builder.build()?)
     }
+
+    /// Brings an existing `CompleteUserInfo` up to date without downloading everything again.
+    /// Review statistics and assignments are only requested if they changed since `previous`
+    /// was fetched, and only subjects we have never seen are looked up. The user, resets and
+    /// level progressions are small enough that they are always fetched in full.
+    ///
+    /// This costs a handful of requests for a user who has done a few reviews, where
+    /// `build_complete_user_info` can cost dozens for a large account.
+    #[tracing::instrument(skip_all)]
+    pub async fn refresh_complete_user_info(
+        &self,
+        previous: &CompleteUserInfo,
+    ) -> Result<CompleteUserInfo, WaniKaniError> {
+        let fetched_at = Local::now();
+        let updated_after = vec![("updated_after", previous.fetched_at.to_rfc3339())];
+        let user_data = self.get_user_data().await?;
+        let review_data: Vec<Response<ReviewStatistic>> = self
+            .get_all_pages_of_paged_data_with_params(
+                &self.url(REVIEW_STATS_PATH),
+                Some(updated_after.clone()),
+            )
+            .await?;
+        let assignment_data: Vec<Response<Assignment>> = self
+            .get_all_pages_of_paged_data_with_params(
+                &self.url(ASSIGNMENT_PATH),
+                Some(updated_after),
+            )
+            .await?;
+        let reset_data = self.get_all_resets().await?;
+        let level_progression_data = self.get_all_level_progressions().await?;
+        let new_subjects: Vec<i32> = self
+            .get_list_of_subjects_to_request(&review_data, &assignment_data)
+            .into_iter()
+            .filter(|id| !previous.id_to_subjects.contains_key(id))
+            .collect();
+
+        let mut id_to_subjects = previous.id_to_subjects.clone();
+
+        // an empty `ids` filter would ask WaniKani for every subject it has
+        if !new_subjects.is_empty() {
+            id_to_subjects.extend(self.construct_id_to_subject_hash(&new_subjects).await?);
+        }
+
+        let builder = CompleteUserInfoBuilder::new(
+            user_data,
+            merge_by_subject_id(
+                &previous.review_stats,
+                review_data.into_iter().map(|response| response.data),
+                |stat| stat.subject_id,
+            ),
+            merge_by_subject_id(
+                &previous.assignments,
+                assignment_data.into_iter().map(|response| response.data),
+                |assignment| assignment.subject_id,
+            ),
+            reset_data
+                .into_iter()
+                .map(|response| response.data)
+                .collect(),
+            id_to_subjects,
+        )
+        .with_level_progressions(
+            level_progression_data
+                .into_iter()
+                .map(|response| response.data)
+                .collect(),
+        )
+        .with_fetched_at(fetched_at);
+
+        Ok(builder.build()?)
+    }
+}
+
+/// WaniKani keeps one review statistic and one assignment per subject, so an updated record
+/// replaces the old one with the same subject id and anything else is new.
+fn merge_by_subject_id<T, F>(
+    previous: &[T],
+    updated: impl Iterator<Item = T>,
+    subject_id: F,
+) -> Vec<T>
+where
+    T: Clone,
+    F: Fn(&T) -> i32,
+{
+    let mut merged: HashMap<i32, T> = previous
+        .iter()
+        .map(|record| (subject_id(record), record.clone()))
+        .collect();
+
+    for record in updated {
+        merged.insert(subject_id(&record), record);
+    }
+
+    merged.into_values().collect()
 }
 
 impl SubjectWithType {
@@ -252,3 +568,76 @@ impl SubjectWithType {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn test_merge_by_subject_id_replaces_and_appends() {
+        let previous = vec![(1, "old"), (2, "unchanged")];
+        let updated = vec![(1, "new"), (3, "added")];
+
+        let mut merged = merge_by_subject_id(&previous, updated.into_iter(), |record| record.0);
+        merged.sort();
+
+        assert_eq!(merged, vec![(1, "new"), (2, "unchanged"), (3, "added")]);
+    }
+
+    #[test]
+    fn test_endpoint_labels_ignore_ids_and_query() {
+        let client = reqwest::Client::new();
+        let limiter = governor::RateLimiter::direct(governor::Quota::per_minute(
+            std::num::NonZeroU32::new(60).unwrap(),
+        ));
+        let api_client = ApiClient::new("token".to_string(), &client, &limiter);
+
+        assert_eq!(
+            api_client.endpoint(&api_client.url("/subjects?ids=1,2,3")),
+            SUBJECT_PATH
+        );
+        assert_eq!(
+            api_client.endpoint(&api_client.url("/review_statistics?page_after_id=42")),
+            REVIEW_STATS_PATH
+        );
+        assert_eq!(
+            api_client.endpoint("https://example.com/elsewhere"),
+            "other"
+        );
+    }
+
+    #[test]
+    fn test_debug_output_hides_token() {
+        let client = reqwest::Client::new();
+        let limiter = governor::RateLimiter::direct(governor::Quota::per_minute(
+            std::num::NonZeroU32::new(60).unwrap(),
+        ));
+        let api_client = ApiClient::new("secret-token".to_string(), &client, &limiter);
+
+        assert!(!format!("{:?}", api_client).contains("secret-token"));
+    }
+
+    #[test]
+    fn test_unauthorized_is_invalid_token() {
+        let err = WaniKaniError::from_status(Some(reqwest::StatusCode::UNAUTHORIZED));
+
+        assert_eq!(err, WaniKaniError::InvalidToken);
+    }
+
+    #[test]
+    fn test_too_many_requests_is_rate_limited() {
+        let err = WaniKaniError::from_status(Some(reqwest::StatusCode::TOO_MANY_REQUESTS));
+
+        assert_eq!(err, WaniKaniError::RateLimited);
+    }
+
+    #[test]
+    fn test_no_response_or_server_error_is_unavailable() {
+        assert_eq!(WaniKaniError::from_status(None), WaniKaniError::Unavailable);
+        assert_eq!(
+            WaniKaniError::from_status(Some(reqwest::StatusCode::BAD_GATEWAY)),
+            WaniKaniError::Unavailable
+        );
+    }
+}
@@ -21,6 +21,12 @@ impl CompleteUserInfo {
         &self.user.started_at
     }
 
+    /// When the data in this struct was requested from WaniKani. Anything that changed on
+    /// WaniKani after this moment is not reflected here.
+    pub fn get_fetched_at(&self) -> &DateTime<Local> {
+        &self.fetched_at
+    }
+
     pub fn get_kanji_learned(&self) -> i32 {
         self.kanji_learned
     }
@@ -29,142 +35,18 @@ impl CompleteUserInfo {
         self.radicals_learned
     }
 
+    /// Vocabulary with kanji in it. Kana vocabulary is counted on its own, and both together are
+    /// `get_all_vocab_learned`.
     pub fn get_vocab_learned(&self) -> i32 {
-        self.kana_learned + self.vocab_learned
-    }
-
-    pub fn get_total_correct_reading_count(&self) -> i32 {
-        self.kanji_stats.num_of_reading_correct
-            + self.vocab_stats.num_of_reading_correct
-            + self.kana_stats.num_of_reading_correct
-    }
-
-    pub fn get_total_correct_meaning_count(&self) -> i32 {
-        self.kanji_stats.num_of_meaning_correct
-            + self.vocab_stats.num_of_meaning_correct
-            + self.kana_stats.num_of_meaning_correct
-            + self.radical_stats.num_of_meaning_correct
-    }
-
-    pub fn get_total_incorrect_reading_count(&self) -> i32 {
-        self.kanji_stats.num_of_reading_incorrect
-            + self.vocab_stats.num_of_reading_incorrect
-            + self.kana_stats.num_of_reading_incorrect
-    }
-
-    pub fn get_total_incorrect_meaning_count(&self) -> i32 {
-        self.kanji_stats.num_of_meaning_incorrect
-            + self.vocab_stats.num_of_meaning_incorrect
-            + self.kana_stats.num_of_meaning_incorrect
-            + self.radical_stats.num_of_meaning_incorrect
-    }
-
-    pub fn get_total_reading_count(&self) -> i32 {
-        self.get_total_correct_reading_count() + self.get_total_incorrect_reading_count()
-    }
-
-    pub fn get_total_meaning_count(&self) -> i32 {
-        self.get_total_correct_meaning_count() + self.get_total_incorrect_meaning_count()
-    }
-
-    pub fn get_total_correct_count(&self) -> i32 {
-        self.get_total_correct_meaning_count() + self.get_total_correct_reading_count()
-    }
-
-    pub fn get_total_incorrect_count(&self) -> i32 {
-        self.get_total_incorrect_meaning_count() + self.get_total_incorrect_reading_count()
-    }
-
-    pub fn get_total_count(&self) -> i32 {
-        self.get_total_reading_count() + self.get_total_meaning_count()
-    }
-
-    pub fn get_total_accuracy(&self) -> f64 {
-        let total_correct = (self.get_total_correct_meaning_count()
-            + self.get_total_correct_reading_count()) as f64;
-
-        (total_correct / self.get_total_count() as f64) * 100.0
-    }
-
-    pub fn get_total_reading_accuracy(&self) -> f64 {
-        (self.get_total_correct_reading_count() as f64 / self.get_total_reading_count() as f64)
-            * 100.0
-    }
-
-    pub fn get_total_meaning_accuracy(&self) -> f64 {
-        (self.get_total_correct_meaning_count() as f64 / self.get_total_meaning_count() as f64)
-            * 100.0
-    }
-
-    pub fn get_radical_meaning_accuracy(&self) -> f64 {
-        let radical_count = (self.radical_stats.num_of_meaning_correct
-            + self.radical_stats.num_of_meaning_incorrect) as f64;
-
-        (self.radical_stats.num_of_meaning_correct as f64 / radical_count) * 100.0
+        self.vocab_learned
     }
 
-    pub fn get_kanji_reading_accuracy(&self) -> f64 {
-        let kanji_count = (self.kanji_stats.num_of_reading_correct
-            + self.kanji_stats.num_of_reading_incorrect) as f64;
-
-        (self.kanji_stats.num_of_reading_correct as f64 / kanji_count) * 100.0
-    }
-
-    pub fn get_kanji_meaning_accuracy(&self) -> f64 {
-        let kanji_count = (self.kanji_stats.num_of_meaning_correct
-            + self.kanji_stats.num_of_meaning_incorrect) as f64;
-
-        (self.kanji_stats.num_of_meaning_correct as f64 / kanji_count) * 100.0
+    pub fn get_kana_vocab_learned(&self) -> i32 {
+        self.kana_learned
     }
 
-    pub fn get_vocab_reading_accuracy(&self) -> f64 {
-        let vocab_count = (self.vocab_stats.num_of_reading_correct
-            + self.vocab_stats.num_of_reading_incorrect
-            + self.kana_stats.num_of_reading_correct
-            + self.kana_stats.num_of_reading_incorrect) as f64;
-        let vocab_correct = (self.vocab_stats.num_of_reading_correct
-            + self.kana_stats.num_of_reading_correct) as f64;
-
-        (vocab_correct / vocab_count) * 100.0
-    }
-
-    pub fn get_vocab_meaning_accuracy(&self) -> f64 {
-        let vocab_count = (self.vocab_stats.num_of_meaning_correct
-            + self.vocab_stats.num_of_meaning_incorrect
-            + self.kana_stats.num_of_meaning_correct
-            + self.kana_stats.num_of_meaning_incorrect) as f64;
-        let vocab_correct = (self.vocab_stats.num_of_meaning_correct
-            + self.kana_stats.num_of_meaning_correct) as f64;
-
-        (vocab_correct / vocab_count) * 100.0
-    }
-
-    pub fn get_kanji_total_accuracy(&self) -> f64 {
-        let kanji_count = (self.kanji_stats.num_of_meaning_correct
-            + self.kanji_stats.num_of_meaning_incorrect
-            + self.kanji_stats.num_of_reading_correct
-            + self.kanji_stats.num_of_reading_incorrect) as f64;
-        let kanji_correct = (self.kanji_stats.num_of_meaning_correct
-            + self.kanji_stats.num_of_reading_correct) as f64;
-
-        (kanji_correct / kanji_count) * 100.0
-    }
-
-    pub fn get_vocab_total_accuracy(&self) -> f64 {
-        let vocab_count = (self.vocab_stats.num_of_meaning_correct
-            + self.vocab_stats.num_of_meaning_incorrect
-            + self.vocab_stats.num_of_reading_correct
-            + self.vocab_stats.num_of_reading_incorrect
-            + self.kana_stats.num_of_meaning_correct
-            + self.kana_stats.num_of_meaning_incorrect
-            + self.kana_stats.num_of_reading_correct
-            + self.kana_stats.num_of_reading_incorrect) as f64;
-        let vocab_correct = (self.vocab_stats.num_of_meaning_correct
-            + self.vocab_stats.num_of_reading_correct
-            + self.kana_stats.num_of_meaning_correct
-            + self.kana_stats.num_of_reading_correct) as f64;
-
-        (vocab_correct / vocab_count) * 100.0
+    pub fn get_all_vocab_learned(&self) -> i32 {
+        self.vocab_learned + self.kana_learned
     }
 }
 
@@ -188,36 +70,56 @@ impl CompleteUserInfoBuilder {
             review_stats,
             assignments,
             resets,
+            level_progressions: Vec::new(),
             id_to_subjects,
+            fetched_at: Local::now(),
         }
     }
 
+    /// Adds the user's level progressions, which only the level-up history needs. Without them the
+    /// history is simply empty.
+    pub fn with_level_progressions(
+        mut self,
+        level_progressions: Vec<LevelProgression>,
+    ) -> CompleteUserInfoBuilder {
+        self.level_progressions = level_progressions;
+        self
+    }
+
+    /// Records when the data going into this builder was requested. This defaults to the
+    /// moment the builder was created, but the `ApiClient` knows better since fetching
+    /// everything can take a while.
+    pub fn with_fetched_at(mut self, fetched_at: DateTime<Local>) -> CompleteUserInfoBuilder {
+        self.fetched_at = fetched_at;
+        self
+    }
+
     /// This method mostly just exists to do all the calculations and return the `CompleteUserInfo`
     /// struct. So realistically, you just call new, add all the data, and then call this method.
     pub fn build(self) -> Result<CompleteUserInfo, MissingSubjectError> {
-        let kanji_stats = self.get_subject_type_stats(&SubjectType::Kanji).unwrap();
-        let vocab_stats = self
-            .get_subject_type_stats(&SubjectType::Vocabulary)
-            .unwrap();
-        let kana_stats = self
-            .get_subject_type_stats(&SubjectType::KanaVocabulary)
-            .unwrap();
-        let radical_stats = self.get_subject_type_stats(&SubjectType::Radical).unwrap();
-        let kanji_learned = self.get_num_of_passed(SubjectType::Kanji).unwrap();
-        let radicals_learned = self.get_num_of_passed(SubjectType::Radical).unwrap();
-        let vocab_learned = self.get_num_of_passed(SubjectType::Vocabulary).unwrap();
-        let kana_learned = self.get_num_of_passed(SubjectType::KanaVocabulary).unwrap();
+        // `CompleteUserInfo::stats` skips anything it can't find the subject for, so this is where a
+        // subject going missing gets noticed
+        if self
+            .review_stats
+            .iter()
+            .any(|review_stat| !self.id_to_subjects.contains_key(&review_stat.subject_id))
+        {
+            return Err(MissingSubjectError);
+        }
+
+        let kanji_learned = self.get_num_of_passed(SubjectType::Kanji)?;
+        let radicals_learned = self.get_num_of_passed(SubjectType::Radical)?;
+        let vocab_learned = self.get_num_of_passed(SubjectType::Vocabulary)?;
+        let kana_learned = self.get_num_of_passed(SubjectType::KanaVocabulary)?;
 
         Ok(CompleteUserInfo {
             user: self.user,
             review_stats: self.review_stats,
             assignments: self.assignments,
             resets: self.resets,
+            level_progressions: self.level_progressions,
             id_to_subjects: self.id_to_subjects,
-            kana_stats,
-            kanji_stats,
-            radical_stats,
-            vocab_stats,
+            fetched_at: self.fetched_at,
             kana_learned,
             kanji_learned,
             radicals_learned,
@@ -244,45 +146,10 @@ impl CompleteUserInfoBuilder {
 
         Ok(result)
     }
-
-    fn get_subject_type_stats(
-        &self,
-        subject: &SubjectType,
-    ) -> Result<SubjectTypeStats, MissingSubjectError> {
-        let mut meaning_correct = 0;
-        let mut meaning_incorrect = 0;
-        let mut reading_correct = 0;
-        let mut reading_incorrect = 0;
-
-        for review_stat in &self.review_stats {
-            let subject_obj = self.id_to_subjects.get(&review_stat.subject_id);
-
-            if subject_obj.is_none() {
-                return Err(MissingSubjectError);
-            }
-
-            let subject_type = &subject_obj.unwrap().subject_type;
-
-            if subject_type == subject {
-                meaning_correct += review_stat.meaning_correct;
-                meaning_incorrect += review_stat.meaning_incorrect;
-                reading_correct += review_stat.reading_correct;
-                reading_incorrect += review_stat.reading_incorrect;
-            }
-        }
-
-        Ok(SubjectTypeStats {
-            subject_type: subject.clone(),
-            num_of_meaning_correct: meaning_correct,
-            num_of_meaning_incorrect: meaning_incorrect,
-            num_of_reading_correct: reading_correct,
-            num_of_reading_incorrect: reading_incorrect,
-        })
-    }
 }
 
 #[cfg(test)]
-mod test {
+pub(super) mod test {
 
     use super::*;
 
@@ -311,6 +178,7 @@ mod test {
         Assignment {
             created_at: Some(Local.with_ymd_and_hms(2023, 10, 1, 12, 0, 0).unwrap()),
             passed_at: Some(Local.with_ymd_and_hms(2023, 10, 2, 12, 0, 0).unwrap()),
+            available_at: None,
             srs_stage: 5,
             subject_id,
         }
@@ -320,6 +188,7 @@ mod test {
         Assignment {
             created_at: Some(Local.with_ymd_and_hms(2023, 10, 1, 12, 0, 0).unwrap()),
             passed_at: None,
+            available_at: None,
             srs_stage: 5,
             subject_id,
         }
@@ -347,6 +216,23 @@ mod test {
                     primary: false,
                 },
             ],
+            readings: match subject_type {
+                "kanji" => vec![
+                    Readings {
+                        reading: "にち".to_string(),
+                        primary: true,
+                    },
+                    Readings {
+                        reading: "ひ".to_string(),
+                        primary: false,
+                    },
+                ],
+                "vocabulary" => vec![Readings {
+                    reading: "たべる".to_string(),
+                    primary: true,
+                }],
+                _ => vec![],
+            },
         }
     }
 
@@ -360,6 +246,36 @@ mod test {
         }
     }
 
+    /// Generates a `CompleteUserInfo` for a user at `level`, with a fake subject for each `(id, type, level)`
+    /// in `subjects`
+    pub fn fake_user_info(
+        level: i32,
+        subjects: &[(i32, SubjectType, i32)],
+        review_stats: Vec<ReviewStatistic>,
+        assignments: Vec<Assignment>,
+    ) -> CompleteUserInfo {
+        let user = User {
+            username: "test".to_string(),
+            level,
+            started_at: Local::now(),
+        };
+        let id_to_subjects = subjects
+            .iter()
+            .map(|&(id, subject_type, level)| {
+                let subject = Subject {
+                    level,
+                    ..fake_subject(subject_type.as_str())
+                };
+
+                (id, SubjectWithType::new(subject, subject_type))
+            })
+            .collect();
+
+        CompleteUserInfoBuilder::new(user, review_stats, assignments, vec![], id_to_subjects)
+            .build()
+            .unwrap()
+    }
+
     fn setup_builder() -> CompleteUserInfoBuilder {
         let user = User {
             username: "test".to_string(),
@@ -426,23 +342,29 @@ mod test {
     }
 
     #[test]
-    fn test_get_subject_type_stats() {
-        let builder = setup_builder();
-        let subject = SubjectType::Kanji;
+    fn test_stats_for_one_type() {
+        let user_info = setup_builder().build().unwrap();
+        let kanji = user_info.stats().types([SubjectType::Kanji]);
 
-        let stats = builder.get_subject_type_stats(&subject).unwrap();
-
-        assert_eq!(stats.subject_type, subject);
-        assert_eq!(stats.num_of_meaning_correct, 50);
-        assert_eq!(stats.num_of_meaning_incorrect, 10);
-        assert_eq!(stats.num_of_reading_correct, 40);
-        assert_eq!(stats.num_of_reading_incorrect, 5);
+        assert_eq!(
+            kanji.clone().question(Question::Meaning).accuracy(),
+            Accuracy {
+                correct: 50,
+                incorrect: 10
+            }
+        );
+        assert_eq!(
+            kanji.question(Question::Reading).accuracy(),
+            Accuracy {
+                correct: 40,
+                incorrect: 5
+            }
+        );
     }
 
     #[test]
-    fn test_insert_review_statistic_and_get_subject_type_stats() {
+    fn test_inserted_review_statistic_counts_toward_stats() {
         let mut builder = setup_builder();
-        let subject = SubjectType::Kanji;
 
         let review_stat = fake_review_statistic(2, "kanji");
         builder.review_stats.push(review_stat);
@@ -451,13 +373,75 @@ mod test {
             SubjectWithType::new(fake_subject("kanji"), SubjectType::Kanji),
         );
 
-        let stats = builder.get_subject_type_stats(&subject).unwrap();
+        let user_info = builder.build().unwrap();
+        let kanji = user_info.stats().types([SubjectType::Kanji]);
+
+        assert_eq!(
+            kanji.clone().question(Question::Meaning).accuracy(),
+            Accuracy {
+                correct: 100,
+                incorrect: 20
+            }
+        );
+        assert_eq!(
+            kanji.question(Question::Reading).accuracy(),
+            Accuracy {
+                correct: 80,
+                incorrect: 10
+            }
+        );
+    }
+
+    #[test]
+    fn test_review_statistic_without_subject_is_an_error() {
+        let mut builder = setup_builder();
+
+        builder.review_stats.push(fake_review_statistic(2, "kanji"));
+
+        assert!(builder.build().is_err());
+    }
+
+    #[test]
+    fn test_assignment_without_subject_is_an_error() {
+        let mut builder = setup_builder();
+
+        builder.assignments.push(fake_assignment(2));
+
+        assert!(builder.build().is_err());
+    }
+
+    #[test]
+    fn test_accuracy_adds_up_questions() {
+        let user_info = setup_builder().build().unwrap();
+
+        let everything = user_info.stats().accuracy();
+
+        assert_eq!(everything.total(), 105);
+        assert_eq!(everything.percentage(), Some(90.0 / 105.0 * 100.0));
+        assert_eq!(everything.to_string(), "85.71%");
+    }
+
+    #[test]
+    fn test_kana_vocabulary_is_learned_separately() {
+        let mut builder = setup_builder();
+
+        for (id, subject_type, fake_type) in [
+            (2, SubjectType::Vocabulary, "vocabulary"),
+            (3, SubjectType::KanaVocabulary, "kana_vocabulary"),
+            (4, SubjectType::KanaVocabulary, "kana_vocabulary"),
+        ] {
+            builder.assignments.push(fake_assignment(id));
+            builder.id_to_subjects.insert(
+                id,
+                SubjectWithType::new(fake_subject(fake_type), subject_type),
+            );
+        }
+
+        let user_info = builder.build().unwrap();
 
-        assert_eq!(stats.subject_type, subject);
-        assert_eq!(stats.num_of_meaning_correct, 100);
-        assert_eq!(stats.num_of_meaning_incorrect, 20);
-        assert_eq!(stats.num_of_reading_correct, 80);
-        assert_eq!(stats.num_of_reading_incorrect, 10);
+        assert_eq!(user_info.get_vocab_learned(), 1);
+        assert_eq!(user_info.get_kana_vocab_learned(), 2);
+        assert_eq!(user_info.get_all_vocab_learned(), 3);
     }
 
     #[test]
@@ -471,6 +455,7 @@ mod test {
         assert_eq!(user_info.get_kanji_learned(), 1);
         assert_eq!(user_info.get_radicals_learned(), 0);
         assert_eq!(user_info.get_vocab_learned(), 0);
+        assert_eq!(user_info.get_kana_vocab_learned(), 0);
         assert_eq!(
             user_info.get_date_of_most_recent_reset(),
             Some(&Local.with_ymd_and_hms(2023, 10, 2, 12, 0, 0).unwrap())
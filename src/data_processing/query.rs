@@ -0,0 +1,137 @@
+use super::*;
+use std::ops::{Bound, RangeBounds};
+
+/// A question about the user's reviews, narrowed down one step at a time, like
+/// `info.stats().types([Kanji, Vocabulary]).levels(1..=10).question(Reading).accuracy()`. Anything that
+/// isn't narrowed down counts everything, so `info.stats().accuracy()` is the user's overall accuracy.
+/// The answer is worked out from the review statistics each time it is asked for, which is a single pass
+/// over subjects the user has reviewed, so there's no need to keep answers around.
+#[derive(Debug, Clone)]
+pub struct StatsQuery<'a> {
+    info: &'a CompleteUserInfo,
+    types: Option<Vec<SubjectType>>,
+    levels: (Bound<i32>, Bound<i32>),
+    question: Option<Question>,
+}
+
+impl CompleteUserInfo {
+    /// Starts a `StatsQuery` over every reviewed subject and both kinds of question.
+    pub fn stats(&self) -> StatsQuery<'_> {
+        StatsQuery {
+            info: self,
+            types: None,
+            levels: (Bound::Unbounded, Bound::Unbounded),
+            question: None,
+        }
+    }
+}
+
+impl StatsQuery<'_> {
+    /// Only counts subjects of these types. Vocabulary and kana vocabulary are separate types, so all of
+    /// the vocabulary means asking for both.
+    pub fn types(mut self, types: impl IntoIterator<Item = SubjectType>) -> Self {
+        self.types = Some(types.into_iter().collect());
+        self
+    }
+
+    /// Only counts subjects from these levels, like `1..=10` or `40..`.
+    pub fn levels(mut self, levels: impl RangeBounds<i32>) -> Self {
+        self.levels = (levels.start_bound().cloned(), levels.end_bound().cloned());
+        self
+    }
+
+    /// Only counts answers to one kind of question.
+    pub fn question(mut self, question: Question) -> Self {
+        self.question = Some(question);
+        self
+    }
+
+    pub fn accuracy(&self) -> Accuracy {
+        self.info
+            .review_stats
+            .iter()
+            .filter_map(|stat| {
+                let subject = self.info.id_to_subjects.get(&stat.subject_id)?;
+                let included = self
+                    .types
+                    .as_ref()
+                    .is_none_or(|types| types.contains(&subject.subject_type))
+                    && self.levels.contains(&subject.subject.level);
+
+                included.then(|| {
+                    let meaning = Accuracy {
+                        correct: stat.meaning_correct,
+                        incorrect: stat.meaning_incorrect,
+                    };
+                    let reading = Accuracy {
+                        correct: stat.reading_correct,
+                        incorrect: stat.reading_incorrect,
+                    };
+
+                    match self.question {
+                        Some(Question::Meaning) => meaning,
+                        Some(Question::Reading) => reading,
+                        None => meaning + reading,
+                    }
+                })
+            })
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::data_processing::complete_user_info::test::{fake_review_statistic, fake_user_info};
+
+    fn user_info() -> CompleteUserInfo {
+        fake_user_info(
+            20,
+            &[
+                (1, SubjectType::Kanji, 5),
+                (2, SubjectType::Kanji, 15),
+                (3, SubjectType::Vocabulary, 5),
+            ],
+            vec![
+                fake_review_statistic(1, "kanji"),
+                fake_review_statistic(2, "kanji"),
+                fake_review_statistic(3, "vocabulary"),
+            ],
+            vec![],
+        )
+    }
+
+    #[test]
+    fn test_each_step_narrows_the_query() {
+        let info = user_info();
+
+        assert_eq!(info.stats().accuracy().total(), 3 * 105);
+        assert_eq!(
+            info.stats().types([SubjectType::Kanji]).accuracy().total(),
+            2 * 105
+        );
+        assert_eq!(
+            info.stats()
+                .types([SubjectType::Kanji, SubjectType::Vocabulary])
+                .levels(1..=10)
+                .question(Question::Reading)
+                .accuracy(),
+            Accuracy {
+                correct: 80,
+                incorrect: 10
+            }
+        );
+        assert_eq!(info.stats().levels(11..).accuracy().total(), 105);
+    }
+
+    #[test]
+    fn test_nothing_matching_has_no_percentage() {
+        let info = user_info();
+
+        let radicals = info.stats().types([SubjectType::Radical]).accuracy();
+
+        assert_eq!(radicals, Accuracy::default());
+        assert_eq!(radicals.percentage(), None);
+    }
+}
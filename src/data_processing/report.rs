@@ -0,0 +1,793 @@
+use super::*;
+use chrono::{Duration, DurationRound};
+use std::collections::{BTreeMap, HashSet};
+
+/// A subject counts as a leech once it has been answered wrong about as often as the current streak can
+/// make up for. This is the threshold most community leech tools settle on.
+pub const LEECH_THRESHOLD: f64 = 1.0;
+
+/// One cell of the accuracy table. Counts and percentages are kept apart so that anything printing
+/// the table can decide for itself how a percentage should look.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum Cell {
+    Count(i32),
+    Percent(f64),
+    /// Nothing to show, either because radicals have no reading or because there haven't been any
+    /// answers to work out an accuracy from.
+    Empty,
+}
+
+impl fmt::Display for Cell {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Cell::Count(count) => write!(f, "{}", count),
+            Cell::Percent(percent) => write!(f, "{:.2}%", percent),
+            Cell::Empty => write!(f, "--"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AccuracyRow {
+    pub label: &'static str,
+    pub reading: Cell,
+    pub meaning: Cell,
+    pub total: Cell,
+}
+
+/// The accuracy table from the /info page, row for row, for anything that shows the stats outside
+/// of the web server.
+pub fn accuracy_table(info: &CompleteUserInfo) -> Vec<AccuracyRow> {
+    let row = |label, reading, meaning, total| AccuracyRow {
+        label,
+        reading,
+        meaning,
+        total,
+    };
+    let percent = |accuracy: Accuracy| accuracy.percentage().map_or(Cell::Empty, Cell::Percent);
+    let type_row = |label, subject_types: &[SubjectType]| {
+        let stats = info.stats().types(subject_types.iter().copied());
+
+        row(
+            label,
+            percent(stats.clone().question(Question::Reading).accuracy()),
+            percent(stats.clone().question(Question::Meaning).accuracy()),
+            percent(stats.accuracy()),
+        )
+    };
+    // radicals and kana vocabulary only have meanings, so their total is the same number
+    let meaning_only_row = |label, subject_type: SubjectType| {
+        let accuracy = percent(info.stats().types([subject_type]).accuracy());
+
+        row(label, Cell::Empty, accuracy, accuracy)
+    };
+    let reading = info.stats().question(Question::Reading).accuracy();
+    let meaning = info.stats().question(Question::Meaning).accuracy();
+    let total = reading + meaning;
+
+    vec![
+        row(
+            "Total Reviews",
+            Cell::Count(reading.total()),
+            Cell::Count(meaning.total()),
+            Cell::Count(total.total()),
+        ),
+        row(
+            "Correct",
+            Cell::Count(reading.correct),
+            Cell::Count(meaning.correct),
+            Cell::Count(total.correct),
+        ),
+        row(
+            "Incorrect",
+            Cell::Count(reading.incorrect),
+            Cell::Count(meaning.incorrect),
+            Cell::Count(total.incorrect),
+        ),
+        row(
+            "Accuracy",
+            percent(reading),
+            percent(meaning),
+            percent(total),
+        ),
+        meaning_only_row("Radicals", SubjectType::Radical),
+        type_row("Kanji", &[SubjectType::Kanji]),
+        type_row("Vocabulary", &[SubjectType::Vocabulary]),
+        meaning_only_row("Kana Vocabulary", SubjectType::KanaVocabulary),
+        type_row(
+            "All Vocabulary",
+            &[SubjectType::Vocabulary, SubjectType::KanaVocabulary],
+        ),
+    ]
+}
+
+/// Everything the /info page shows, gathered up in one place so it can be serialized as a whole.
+#[derive(Debug, Clone, Serialize)]
+pub struct StatsSummary {
+    pub username: String,
+    pub level: i32,
+    pub started_at: DateTime<Local>,
+    pub reset_count: i32,
+    pub most_recent_reset: Option<DateTime<Local>>,
+    pub radicals_learned: i32,
+    pub kanji_learned: i32,
+    pub vocab_learned: i32,
+    pub kana_vocab_learned: i32,
+    /// Vocabulary and kana vocabulary together.
+    pub all_vocab_learned: i32,
+    pub fetched_at: DateTime<Local>,
+    pub accuracy: Vec<AccuracyRow>,
+    pub resets: Vec<ResetEvent>,
+    pub reset_progress: ResetProgress,
+    pub level_times: Vec<LevelTime>,
+}
+
+impl From<&CompleteUserInfo> for StatsSummary {
+    fn from(info: &CompleteUserInfo) -> Self {
+        StatsSummary {
+            username: info.get_user_name().to_string(),
+            level: info.get_level(),
+            started_at: *info.get_started_at(),
+            reset_count: info.get_num_of_resets(),
+            most_recent_reset: info.get_date_of_most_recent_reset().copied(),
+            radicals_learned: info.get_radicals_learned(),
+            kanji_learned: info.get_kanji_learned(),
+            vocab_learned: info.get_vocab_learned(),
+            kana_vocab_learned: info.get_kana_vocab_learned(),
+            all_vocab_learned: info.get_all_vocab_learned(),
+            fetched_at: *info.get_fetched_at(),
+            accuracy: accuracy_table(info),
+            resets: reset_history(info),
+            reset_progress: reset_progress(info),
+            level_times: level_times(info, *info.get_fetched_at()),
+        }
+    }
+}
+
+/// The SRS stages grouped the way the WaniKani dashboard shows them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum SrsGroup {
+    Apprentice,
+    Guru,
+    Master,
+    Enlightened,
+    Burned,
+}
+
+impl SrsGroup {
+    pub const ALL: [SrsGroup; 5] = [
+        SrsGroup::Apprentice,
+        SrsGroup::Guru,
+        SrsGroup::Master,
+        SrsGroup::Enlightened,
+        SrsGroup::Burned,
+    ];
+
+    /// Stage 0 is a subject still waiting in lessons, which isn't in any group yet.
+    pub(crate) fn from_stage(stage: i32) -> Option<SrsGroup> {
+        match stage {
+            1..=4 => Some(SrsGroup::Apprentice),
+            5 | 6 => Some(SrsGroup::Guru),
+            7 => Some(SrsGroup::Master),
+            8 => Some(SrsGroup::Enlightened),
+            9 => Some(SrsGroup::Burned),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for SrsGroup {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+/// How many started subjects are in each SRS group, in the order they are passed through.
+pub fn srs_distribution(info: &CompleteUserInfo) -> Vec<(SrsGroup, i32)> {
+    let mut counts = [0; SrsGroup::ALL.len()];
+
+    for group in info
+        .assignments
+        .iter()
+        .filter_map(|assignment| SrsGroup::from_stage(assignment.srs_stage))
+    {
+        counts[group as usize] += 1;
+    }
+
+    SrsGroup::ALL.into_iter().zip(counts).collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ForecastHour {
+    pub starts_at: DateTime<Local>,
+    pub reviews: i32,
+    /// Everything that will be waiting by the end of this hour if no reviews get done before then.
+    pub cumulative: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ReviewForecast {
+    pub available_now: i32,
+    pub hours: Vec<ForecastHour>,
+}
+
+/// Reviews that are waiting right now, and how many more come due in each of the next `hours` hours.
+/// The first hour is the one `now` falls in, so it is usually only partly still to come.
+pub fn review_forecast(
+    info: &CompleteUserInfo,
+    now: DateTime<Local>,
+    hours: usize,
+) -> ReviewForecast {
+    let first_hour = now.duration_trunc(Duration::hours(1)).unwrap_or(now);
+    let mut available_now = 0;
+    let mut per_hour = vec![0; hours];
+
+    for available_at in info
+        .assignments
+        .iter()
+        .filter_map(|assignment| assignment.available_at)
+    {
+        if available_at <= now {
+            available_now += 1;
+            continue;
+        }
+
+        let hour = (available_at - first_hour).num_hours() as usize;
+
+        if let Some(count) = per_hour.get_mut(hour) {
+            *count += 1;
+        }
+    }
+
+    let mut cumulative = available_now;
+    let hours = per_hour
+        .into_iter()
+        .enumerate()
+        .map(|(hour, reviews)| {
+            cumulative += reviews;
+
+            ForecastHour {
+                starts_at: first_hour + Duration::hours(hour as i64),
+                reviews,
+                cumulative,
+            }
+        })
+        .collect();
+
+    ReviewForecast {
+        available_now,
+        hours,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Leech {
+    pub subject_id: i32,
+    pub characters: String,
+    pub meaning: String,
+    pub subject_type: &'static str,
+    /// How many times it has been answered wrong, for whichever of meaning or reading is worse.
+    pub incorrect: i32,
+    pub score: f64,
+}
+
+/// Subjects that keep getting answered wrong, worst first. A subject's score is how often it has been
+/// answered wrong divided by its current streak to the power of 1.5, taking whichever of meaning and
+/// reading scores worse, so a long streak slowly clears an old leech. Burned subjects are left out since
+/// there is nothing more to do about them.
+pub fn leeches(info: &CompleteUserInfo) -> Vec<Leech> {
+    let burned: HashSet<i32> = info
+        .assignments
+        .iter()
+        .filter(|assignment| SrsGroup::from_stage(assignment.srs_stage) == Some(SrsGroup::Burned))
+        .map(|assignment| assignment.subject_id)
+        .collect();
+    let score = |incorrect: i32, streak: i32| incorrect as f64 / (streak.max(1) as f64).powf(1.5);
+
+    let mut leeches: Vec<Leech> = info
+        .review_stats
+        .iter()
+        .filter(|stat| !burned.contains(&stat.subject_id))
+        .filter_map(|stat| {
+            let subject = info.id_to_subjects.get(&stat.subject_id)?;
+            let meaning_score = score(stat.meaning_incorrect, stat.meaning_current_streak);
+            let reading_score = score(stat.reading_incorrect, stat.reading_current_streak);
+            let (score, incorrect) = if meaning_score >= reading_score {
+                (meaning_score, stat.meaning_incorrect)
+            } else {
+                (reading_score, stat.reading_incorrect)
+            };
+
+            (score >= LEECH_THRESHOLD).then(|| Leech {
+                subject_id: stat.subject_id,
+                characters: subject.subject.display_characters(),
+                meaning: subject
+                    .subject
+                    .primary_meaning()
+                    .unwrap_or_default()
+                    .to_string(),
+                subject_type: subject.subject_type.as_str(),
+                incorrect,
+                score,
+            })
+        })
+        .collect();
+
+    leeches.sort_by(|a, b| b.score.total_cmp(&a.score));
+
+    leeches
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LevelUp {
+    pub level: i32,
+    pub unlocked_at: DateTime<Local>,
+    /// Empty for the level the user is on now.
+    pub passed_at: Option<DateTime<Local>>,
+    /// Days from unlocking the level to passing it, or to `now` for the current level.
+    pub days: f64,
+}
+
+/// How long each level took, oldest first. Levels a reset took the user back past are left out, so after
+/// a reset this is the history of the current run through the levels.
+pub fn level_ups(info: &CompleteUserInfo, now: DateTime<Local>) -> Vec<LevelUp> {
+    let mut level_ups: Vec<LevelUp> = info
+        .level_progressions
+        .iter()
+        .filter(|progression| progression.abandoned_at.is_none())
+        .filter_map(|progression| {
+            let unlocked_at = progression.unlocked_at?;
+            let until = progression.passed_at.unwrap_or(now);
+
+            Some(LevelUp {
+                level: progression.level,
+                unlocked_at,
+                passed_at: progression.passed_at,
+                days: (until - unlocked_at).num_seconds() as f64 / 86_400.0,
+            })
+        })
+        .collect();
+
+    level_ups.sort_by_key(|level_up| level_up.level);
+
+    level_ups
+}
+
+/// How many subjects were passed at `since` or later.
+pub fn items_passed_since(info: &CompleteUserInfo, since: DateTime<Local>) -> i32 {
+    info.assignments
+        .iter()
+        .filter(|assignment| {
+            assignment
+                .passed_at
+                .is_some_and(|passed_at| passed_at >= since)
+        })
+        .count() as i32
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ResetEvent {
+    pub original_level: i32,
+    pub target_level: i32,
+    pub confirmed_at: DateTime<Local>,
+}
+
+/// Every reset the user has gone through, oldest first.
+pub fn reset_history(info: &CompleteUserInfo) -> Vec<ResetEvent> {
+    let mut resets: Vec<ResetEvent> = info
+        .resets
+        .iter()
+        .map(|reset| ResetEvent {
+            original_level: reset.original_level,
+            target_level: reset.target_level,
+            confirmed_at: reset.confirmed_at,
+        })
+        .collect();
+
+    resets.sort_by_key(|reset| reset.confirmed_at);
+
+    resets
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LevelTime {
+    pub level: i32,
+    /// How many times the user has been on the level, counting the time they're on it now.
+    pub times: i32,
+    /// Days spent on the level over every time through it.
+    pub total_days: f64,
+    /// Days spent on the level since the last reset, empty if a reset took the user back past it and they
+    /// haven't got to it again yet.
+    pub current_days: Option<f64>,
+}
+
+/// How long the user has spent on each level over every run through the levels, not just the current one
+/// like `level_ups`. Time on a level ends when it was passed, or else when a reset took it away.
+pub fn level_times(info: &CompleteUserInfo, now: DateTime<Local>) -> Vec<LevelTime> {
+    let mut levels: BTreeMap<i32, LevelTime> = BTreeMap::new();
+
+    for progression in &info.level_progressions {
+        let Some(unlocked_at) = progression.unlocked_at else {
+            continue;
+        };
+        let until = progression
+            .passed_at
+            .or(progression.abandoned_at)
+            .unwrap_or(now);
+        let days = (until - unlocked_at).num_seconds() as f64 / 86_400.0;
+        let level = levels.entry(progression.level).or_insert(LevelTime {
+            level: progression.level,
+            times: 0,
+            total_days: 0.0,
+            current_days: None,
+        });
+
+        level.times += 1;
+        level.total_days += days;
+
+        if progression.abandoned_at.is_none() {
+            level.current_days = Some(days);
+        }
+    }
+
+    levels.into_values().collect()
+}
+
+/// The current run through the levels next to everything the user has done, for anyone who has reset.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ResetProgress {
+    /// Where the current run starts: the most recent reset, or when the user started if they never have.
+    pub since: DateTime<Local>,
+    pub levels_passed_since: i32,
+    /// Includes levels passed and then taken away again by a reset.
+    pub levels_passed_all_time: i32,
+    pub items_passed_since: i32,
+    /// Every subject that is passed right now, including the ones passed before the last reset and kept.
+    /// There is no all time count of items to go with the one for levels, since WaniKani forgets a subject
+    /// was passed when a reset takes it away.
+    pub items_passed_now: i32,
+}
+
+pub fn reset_progress(info: &CompleteUserInfo) -> ResetProgress {
+    let since = *info
+        .get_date_of_most_recent_reset()
+        .unwrap_or(info.get_started_at());
+    let passed_levels = info
+        .level_progressions
+        .iter()
+        .filter(|progression| progression.passed_at.is_some());
+
+    ResetProgress {
+        since,
+        levels_passed_since: passed_levels
+            .clone()
+            .filter(|progression| {
+                progression
+                    .passed_at
+                    .is_some_and(|passed_at| passed_at >= since)
+            })
+            .count() as i32,
+        levels_passed_all_time: passed_levels.count() as i32,
+        items_passed_since: items_passed_since(info, since),
+        items_passed_now: info
+            .assignments
+            .iter()
+            .filter(|assignment| assignment.passed_at.is_some())
+            .count() as i32,
+    }
+}
+
+/// One assigned subject along with how the user has done on it. The review columns are empty for subjects
+/// that haven't been reviewed yet, which is different from having been reviewed and never missed.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ItemRow {
+    pub subject_id: i32,
+    pub subject_type: &'static str,
+    pub characters: String,
+    pub meaning: String,
+    pub level: i32,
+    pub srs_stage: i32,
+    pub passed_at: Option<DateTime<Local>>,
+    pub meaning_correct: Option<i32>,
+    pub meaning_incorrect: Option<i32>,
+    pub meaning_current_streak: Option<i32>,
+    pub meaning_max_streak: Option<i32>,
+    pub reading_correct: Option<i32>,
+    pub reading_incorrect: Option<i32>,
+    pub reading_current_streak: Option<i32>,
+    pub reading_max_streak: Option<i32>,
+}
+
+/// Every assigned subject, by level and then by id so that two exports of the same account line up.
+/// Flat on purpose, since this is what ends up in the CSV export and each field becomes a column.
+pub fn item_rows(info: &CompleteUserInfo) -> Vec<ItemRow> {
+    let review_stats: HashMap<i32, &ReviewStatistic> = info
+        .review_stats
+        .iter()
+        .map(|stat| (stat.subject_id, stat))
+        .collect();
+
+    let mut rows: Vec<ItemRow> = info
+        .assignments
+        .iter()
+        .filter_map(|assignment| {
+            let subject = info.id_to_subjects.get(&assignment.subject_id)?;
+            let stat = review_stats.get(&assignment.subject_id);
+
+            Some(ItemRow {
+                subject_id: assignment.subject_id,
+                subject_type: subject.subject_type.as_str(),
+                characters: subject.subject.display_characters(),
+                meaning: subject
+                    .subject
+                    .primary_meaning()
+                    .unwrap_or_default()
+                    .to_string(),
+                level: subject.subject.level,
+                srs_stage: assignment.srs_stage,
+                passed_at: assignment.passed_at,
+                meaning_correct: stat.map(|stat| stat.meaning_correct),
+                meaning_incorrect: stat.map(|stat| stat.meaning_incorrect),
+                meaning_current_streak: stat.map(|stat| stat.meaning_current_streak),
+                meaning_max_streak: stat.map(|stat| stat.meaning_max_streak),
+                reading_correct: stat.map(|stat| stat.reading_correct),
+                reading_incorrect: stat.map(|stat| stat.reading_incorrect),
+                reading_current_streak: stat.map(|stat| stat.reading_current_streak),
+                reading_max_streak: stat.map(|stat| stat.reading_max_streak),
+            })
+        })
+        .collect();
+
+    rows.sort_by_key(|row| (row.level, row.subject_id));
+
+    rows
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::data_processing::complete_user_info::test::{
+        fake_assignment, fake_non_passed_assignment, fake_reset, fake_review_statistic,
+        fake_subject, fake_user_info,
+    };
+    use chrono::TimeZone;
+
+    fn kanji_only_user_info() -> CompleteUserInfo {
+        fake_user_info(
+            1,
+            &[(1, SubjectType::Kanji, 5)],
+            vec![fake_review_statistic(1, "kanji")],
+            vec![fake_assignment(1)],
+        )
+    }
+
+    #[test]
+    fn test_accuracy_table_matches_the_query() {
+        let info = kanji_only_user_info();
+
+        let table = accuracy_table(&info);
+
+        assert_eq!(table.len(), 9);
+        assert_eq!(
+            table[0],
+            AccuracyRow {
+                label: "Total Reviews",
+                reading: Cell::Count(45),
+                meaning: Cell::Count(60),
+                total: Cell::Count(105),
+            }
+        );
+        assert_eq!(
+            table[5].total,
+            Cell::Percent(
+                info.stats()
+                    .types([SubjectType::Kanji])
+                    .accuracy()
+                    .percentage()
+                    .unwrap()
+            )
+        );
+        assert_eq!(table[4].reading, Cell::Empty);
+        // no radicals have been reviewed, which used to come out as NaN
+        assert_eq!(table[4].meaning, Cell::Empty);
+    }
+
+    #[test]
+    fn test_srs_distribution_groups_stages() {
+        let mut info = kanji_only_user_info();
+        info.assignments = [0, 1, 4, 5, 9]
+            .into_iter()
+            .map(|stage| Assignment {
+                srs_stage: stage,
+                ..fake_assignment(1)
+            })
+            .collect();
+
+        let distribution = srs_distribution(&info);
+
+        assert_eq!(
+            distribution,
+            vec![
+                (SrsGroup::Apprentice, 2),
+                (SrsGroup::Guru, 1),
+                (SrsGroup::Master, 0),
+                (SrsGroup::Enlightened, 0),
+                (SrsGroup::Burned, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_review_forecast_buckets_by_hour() {
+        let now = Local.with_ymd_and_hms(2024, 5, 1, 10, 30, 0).unwrap();
+        let mut info = kanji_only_user_info();
+        info.assignments = [
+            Some(now - Duration::hours(2)),
+            Some(now + Duration::minutes(10)),
+            Some(now + Duration::hours(2)),
+            Some(now + Duration::hours(30)),
+            None,
+        ]
+        .into_iter()
+        .map(|available_at| Assignment {
+            available_at,
+            ..fake_assignment(1)
+        })
+        .collect();
+
+        let forecast = review_forecast(&info, now, 24);
+
+        assert_eq!(forecast.available_now, 1);
+        assert_eq!(forecast.hours.len(), 24);
+        assert_eq!(
+            forecast.hours[0].starts_at,
+            Local.with_ymd_and_hms(2024, 5, 1, 10, 0, 0).unwrap()
+        );
+        assert_eq!(forecast.hours[0].reviews, 1);
+        assert_eq!(forecast.hours[2].reviews, 1);
+        assert_eq!(forecast.hours[23].cumulative, 3);
+    }
+
+    #[test]
+    fn test_leeches_are_scored_and_burned_ones_skipped() {
+        let mut info = kanji_only_user_info();
+        info.review_stats = vec![ReviewStatistic {
+            reading_incorrect: 8,
+            reading_current_streak: 1,
+            ..fake_review_statistic(1, "kanji")
+        }];
+
+        let found = leeches(&info);
+
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].characters, "日");
+        assert_eq!(found[0].incorrect, 8);
+        assert_eq!(found[0].score, 8.0);
+
+        info.assignments[0].srs_stage = 9;
+
+        assert!(leeches(&info).is_empty());
+    }
+
+    #[test]
+    fn test_item_rows_join_assignments_with_review_stats() {
+        let mut info = kanji_only_user_info();
+        info.assignments.push(fake_assignment(2));
+        info.id_to_subjects.insert(
+            2,
+            SubjectWithType::new(fake_subject("radical"), SubjectType::Radical),
+        );
+
+        let rows = item_rows(&info);
+
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].subject_type, "kanji");
+        assert_eq!(rows[0].characters, "日");
+        assert_eq!(rows[0].meaning, "one");
+        assert_eq!(rows[0].reading_incorrect, Some(5));
+        assert_eq!(rows[0].meaning_max_streak, Some(10));
+        // subject 2 has an assignment but has never been reviewed
+        assert_eq!(rows[1].subject_type, "radical");
+        assert_eq!(rows[1].meaning_correct, None);
+    }
+
+    #[test]
+    fn test_level_ups_skip_abandoned_levels() {
+        let now = Local.with_ymd_and_hms(2024, 5, 11, 0, 0, 0).unwrap();
+        let progression =
+            |level, unlocked_day, passed_day: Option<u32>, abandoned: bool| LevelProgression {
+                level,
+                unlocked_at: Some(
+                    Local
+                        .with_ymd_and_hms(2024, 5, unlocked_day, 0, 0, 0)
+                        .unwrap(),
+                ),
+                passed_at: passed_day
+                    .map(|day| Local.with_ymd_and_hms(2024, 5, day, 0, 0, 0).unwrap()),
+                abandoned_at: abandoned.then_some(now),
+            };
+        let mut info = kanji_only_user_info();
+        info.level_progressions = vec![
+            progression(2, 8, None, false),
+            progression(1, 1, Some(8), false),
+            progression(3, 1, None, true),
+        ];
+
+        let history = level_ups(&info, now);
+
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].level, 1);
+        assert_eq!(history[0].days, 7.0);
+        assert_eq!(history[1].passed_at, None);
+        assert_eq!(history[1].days, 3.0);
+    }
+
+    #[test]
+    fn test_items_passed_since_counts_recent_passes() {
+        let mut info = kanji_only_user_info();
+        info.assignments.push(fake_non_passed_assignment(1));
+
+        let passed_at = fake_assignment(1).passed_at.unwrap();
+
+        assert_eq!(items_passed_since(&info, passed_at), 1);
+        assert_eq!(
+            items_passed_since(&info, passed_at + Duration::seconds(1)),
+            0
+        );
+    }
+
+    #[test]
+    fn test_level_times_add_up_every_run() {
+        let now = Local.with_ymd_and_hms(2024, 5, 20, 0, 0, 0).unwrap();
+        let day = |day| Local.with_ymd_and_hms(2024, 5, day, 0, 0, 0).unwrap();
+        let mut info = kanji_only_user_info();
+        info.level_progressions = vec![
+            // the first time through level 1, and level 2 cut short by a reset back to 1
+            LevelProgression {
+                level: 1,
+                unlocked_at: Some(day(1)),
+                passed_at: Some(day(5)),
+                abandoned_at: Some(day(10)),
+            },
+            LevelProgression {
+                level: 2,
+                unlocked_at: Some(day(5)),
+                passed_at: None,
+                abandoned_at: Some(day(10)),
+            },
+            LevelProgression {
+                level: 1,
+                unlocked_at: Some(day(10)),
+                passed_at: Some(day(12)),
+                abandoned_at: None,
+            },
+        ];
+        info.resets = vec![Reset {
+            confirmed_at: day(10),
+            ..fake_reset()
+        }];
+
+        let times = level_times(&info, now);
+
+        assert_eq!(times[0].times, 2);
+        assert_eq!(times[0].total_days, 6.0);
+        assert_eq!(times[0].current_days, Some(2.0));
+        assert_eq!(times[1].total_days, 5.0);
+        assert_eq!(times[1].current_days, None);
+
+        let progress = reset_progress(&info);
+
+        assert_eq!(progress.since, day(10));
+        assert_eq!(progress.levels_passed_since, 1);
+        assert_eq!(progress.levels_passed_all_time, 2);
+    }
+
+    #[test]
+    fn test_cells_display_like_the_info_page() {
+        assert_eq!(Cell::Count(12).to_string(), "12");
+        assert_eq!(Cell::Percent(87.5).to_string(), "87.50%");
+        assert_eq!(Cell::Empty.to_string(), "--");
+    }
+}
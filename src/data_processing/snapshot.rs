@@ -0,0 +1,307 @@
+use super::report::{leeches, SrsGroup};
+use super::*;
+use std::collections::HashSet;
+
+/// Where one subject stood when a snapshot was taken. Snapshots are kept for months, so this is only what
+/// changes from day to day; what the subject is called comes out of the user's latest data when it is shown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SubjectState {
+    pub srs_stage: i32,
+    pub passed: bool,
+    pub leech: bool,
+}
+
+/// The parts of a `CompleteUserInfo` that are worth comparing later on. A whole `CompleteUserInfo` carries
+/// every subject WaniKani has, most of which the user hasn't reached, so this only keeps the assigned ones.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub taken_at: DateTime<Local>,
+    pub level: i32,
+    /// Ordered by subject id. A sorted `Vec` rather than a map, since there can be thousands of these in
+    /// every snapshot.
+    pub subjects: Vec<(i32, SubjectState)>,
+    /// Overall accuracy and then accuracy for each subject type, as the accuracy table labels them.
+    pub accuracy: Vec<(&'static str, Accuracy)>,
+}
+
+impl Snapshot {
+    pub fn new(info: &CompleteUserInfo) -> Self {
+        let leech_ids: HashSet<i32> = leeches(info)
+            .into_iter()
+            .map(|leech| leech.subject_id)
+            .collect();
+
+        let mut subjects: Vec<(i32, SubjectState)> = info
+            .assignments
+            .iter()
+            .map(|assignment| {
+                let state = SubjectState {
+                    srs_stage: assignment.srs_stage,
+                    passed: assignment.passed_at.is_some(),
+                    leech: leech_ids.contains(&assignment.subject_id),
+                };
+
+                (assignment.subject_id, state)
+            })
+            .collect();
+
+        subjects.sort_unstable_by_key(|(subject_id, _)| *subject_id);
+
+        Snapshot {
+            taken_at: info.fetched_at,
+            level: info.get_level(),
+            subjects,
+            accuracy: vec![
+                ("Total", info.stats().accuracy()),
+                (
+                    "Radicals",
+                    info.stats().types([SubjectType::Radical]).accuracy(),
+                ),
+                ("Kanji", info.stats().types([SubjectType::Kanji]).accuracy()),
+                (
+                    "Vocabulary",
+                    info.stats().types([SubjectType::Vocabulary]).accuracy(),
+                ),
+                (
+                    "Kana Vocabulary",
+                    info.stats().types([SubjectType::KanaVocabulary]).accuracy(),
+                ),
+                (
+                    "All Vocabulary",
+                    info.stats()
+                        .types([SubjectType::Vocabulary, SubjectType::KanaVocabulary])
+                        .accuracy(),
+                ),
+            ],
+        }
+    }
+
+    fn subject(&self, subject_id: i32) -> Option<&SubjectState> {
+        self.subjects
+            .binary_search_by_key(&subject_id, |(id, _)| *id)
+            .ok()
+            .map(|index| &self.subjects[index].1)
+    }
+
+    fn srs_counts(&self) -> [i32; SrsGroup::ALL.len()] {
+        let mut counts = [0; SrsGroup::ALL.len()];
+
+        for group in self
+            .subjects
+            .iter()
+            .filter_map(|(_, state)| SrsGroup::from_stage(state.srs_stage))
+        {
+            counts[group as usize] += 1;
+        }
+
+        counts
+    }
+}
+
+/// A subject that changed between two snapshots.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ItemChange {
+    pub subject_id: i32,
+    pub characters: String,
+    pub meaning: String,
+    pub subject_type: &'static str,
+    pub level: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AccuracyChange {
+    pub label: &'static str,
+    pub from: Option<f64>,
+    pub to: Option<f64>,
+    /// In percentage points. Empty if either side had no reviews to work out an accuracy from.
+    pub change: Option<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SrsChange {
+    pub group: SrsGroup,
+    pub from: i32,
+    pub to: i32,
+}
+
+/// Everything that happened between two snapshots.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SnapshotDiff {
+    pub from: DateTime<Local>,
+    pub to: DateTime<Local>,
+    pub level_from: i32,
+    pub level_to: i32,
+    pub newly_passed: Vec<ItemChange>,
+    pub newly_burned: Vec<ItemChange>,
+    pub newly_leeched: Vec<ItemChange>,
+    pub accuracy: Vec<AccuracyChange>,
+    pub srs: Vec<SrsChange>,
+    /// Subjects whose SRS stage went up, which includes ones started since `from`.
+    pub moved_up: i32,
+    pub moved_down: i32,
+}
+
+/// Compares two snapshots, `from` being the older one. Subjects in the lists are described with `info`,
+/// the user's latest data, and ordered by level and then by id, the same as the item export.
+pub fn compare(from: &Snapshot, to: &Snapshot, info: &CompleteUserInfo) -> SnapshotDiff {
+    let mut newly_passed = Vec::new();
+    let mut newly_burned = Vec::new();
+    let mut newly_leeched = Vec::new();
+    let mut moved_up = 0;
+    let mut moved_down = 0;
+
+    for (subject_id, state) in &to.subjects {
+        let before = from.subject(*subject_id);
+        let change = || {
+            let subject = info.id_to_subjects.get(subject_id)?;
+
+            Some(ItemChange {
+                subject_id: *subject_id,
+                characters: subject.subject.display_characters(),
+                meaning: subject
+                    .subject
+                    .primary_meaning()
+                    .unwrap_or_default()
+                    .to_string(),
+                subject_type: subject.subject_type.as_str(),
+                level: subject.subject.level,
+            })
+        };
+
+        if state.passed && !before.is_some_and(|before| before.passed) {
+            newly_passed.extend(change());
+        }
+
+        if SrsGroup::from_stage(state.srs_stage) == Some(SrsGroup::Burned)
+            && !before.is_some_and(|before| {
+                SrsGroup::from_stage(before.srs_stage) == Some(SrsGroup::Burned)
+            })
+        {
+            newly_burned.extend(change());
+        }
+
+        if state.leech && !before.is_some_and(|before| before.leech) {
+            newly_leeched.extend(change());
+        }
+
+        let stage_before = before.map(|before| before.srs_stage).unwrap_or(0);
+
+        if state.srs_stage > stage_before {
+            moved_up += 1;
+        } else if state.srs_stage < stage_before {
+            moved_down += 1;
+        }
+    }
+
+    for changes in [&mut newly_passed, &mut newly_burned, &mut newly_leeched] {
+        changes.sort_by_key(|change| (change.level, change.subject_id));
+    }
+
+    let accuracy = from
+        .accuracy
+        .iter()
+        .zip(&to.accuracy)
+        .map(|(&(label, from), &(_, to))| AccuracyChange {
+            label,
+            from: from.percentage(),
+            to: to.percentage(),
+            change: to
+                .percentage()
+                .zip(from.percentage())
+                .map(|(to, from)| to - from),
+        })
+        .collect();
+
+    let srs = SrsGroup::ALL
+        .into_iter()
+        .zip(from.srs_counts().into_iter().zip(to.srs_counts()))
+        .map(|(group, (from, to))| SrsChange { group, from, to })
+        .collect();
+
+    SnapshotDiff {
+        from: from.taken_at,
+        to: to.taken_at,
+        level_from: from.level,
+        level_to: to.level,
+        newly_passed,
+        newly_burned,
+        newly_leeched,
+        accuracy,
+        srs,
+        moved_up,
+        moved_down,
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::data_processing::complete_user_info::test::{
+        fake_assignment, fake_non_passed_assignment, fake_review_statistic, fake_user_info,
+    };
+
+    fn user_info(
+        assignments: Vec<Assignment>,
+        review_stats: Vec<ReviewStatistic>,
+    ) -> CompleteUserInfo {
+        fake_user_info(
+            5,
+            &[(1, SubjectType::Kanji, 5), (2, SubjectType::Vocabulary, 5)],
+            review_stats,
+            assignments,
+        )
+    }
+
+    #[test]
+    fn test_compare_finds_what_changed() {
+        let before = Snapshot::new(&user_info(
+            vec![fake_non_passed_assignment(1), fake_assignment(2)],
+            vec![
+                fake_review_statistic(1, "kanji"),
+                fake_review_statistic(2, "vocabulary"),
+            ],
+        ));
+        let burned = Assignment {
+            srs_stage: 9,
+            ..fake_assignment(2)
+        };
+        let leech = ReviewStatistic {
+            meaning_incorrect: 12,
+            meaning_current_streak: 1,
+            ..fake_review_statistic(1, "kanji")
+        };
+        let latest = user_info(
+            vec![fake_assignment(1), burned],
+            vec![leech, fake_review_statistic(2, "vocabulary")],
+        );
+        let after = Snapshot::new(&latest);
+
+        let diff = compare(&before, &after, &latest);
+
+        assert_eq!(diff.newly_passed.len(), 1);
+        assert_eq!(diff.newly_passed[0].subject_id, 1);
+        assert_eq!(diff.newly_burned[0].characters, "食べる");
+        assert_eq!(diff.newly_leeched[0].subject_id, 1);
+        assert_eq!(diff.moved_up, 1);
+        assert_eq!(diff.moved_down, 0);
+        assert_eq!(diff.srs[SrsGroup::Guru as usize].from, 2);
+        assert_eq!(diff.srs[SrsGroup::Guru as usize].to, 1);
+        assert_eq!(diff.srs[SrsGroup::Burned as usize].to, 1);
+        assert!(diff.accuracy[2].change.unwrap() < 0.0);
+        // nobody has reviewed a radical
+        assert_eq!(diff.accuracy[1].change, None);
+    }
+
+    #[test]
+    fn test_nothing_changes_against_itself() {
+        let info = user_info(vec![fake_assignment(1), fake_assignment(2)], vec![]);
+        let snapshot = Snapshot::new(&info);
+
+        let diff = compare(&snapshot, &snapshot, &info);
+
+        assert!(diff.newly_passed.is_empty());
+        assert!(diff.newly_burned.is_empty());
+        assert_eq!((diff.moved_up, diff.moved_down), (0, 0));
+    }
+}
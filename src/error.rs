@@ -0,0 +1,216 @@
+use axum::{
+    http::StatusCode,
+    response::{Html, IntoResponse, Response},
+};
+use minijinja::{context, Environment};
+use std::fmt;
+use std::sync::OnceLock;
+use wanikani_stats::data_processing::WaniKaniError;
+
+/// Everything a handler can fail with once the user has gotten past the login form. Handlers return
+/// `Result<_, AppError>` and `?` their way through, and the error turns itself into the error page with
+/// a status code and a message that tells the user whether waiting will help.
+#[derive(Debug)]
+pub enum AppError {
+    WaniKani(WaniKaniError),
+    Template(minijinja::Error),
+    Export(csv::Error),
+    InvalidCsrfToken,
+}
+
+impl AppError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AppError::WaniKani(WaniKaniError::InvalidToken)
+            | AppError::WaniKani(WaniKaniError::MissingReadAccess(_)) => StatusCode::UNAUTHORIZED,
+            AppError::WaniKani(WaniKaniError::RateLimited) => StatusCode::TOO_MANY_REQUESTS,
+            AppError::WaniKani(WaniKaniError::Unavailable)
+            | AppError::WaniKani(WaniKaniError::UnexpectedResponse) => StatusCode::BAD_GATEWAY,
+            AppError::Template(_) | AppError::Export(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::InvalidCsrfToken => StatusCode::FORBIDDEN,
+        }
+    }
+
+    fn title(&self) -> &'static str {
+        match self {
+            AppError::WaniKani(WaniKaniError::InvalidToken)
+            | AppError::WaniKani(WaniKaniError::MissingReadAccess(_)) => {
+                "Your API token was rejected"
+            }
+            AppError::WaniKani(WaniKaniError::RateLimited) => "Slow down a little",
+            AppError::WaniKani(WaniKaniError::Unavailable)
+            | AppError::WaniKani(WaniKaniError::UnexpectedResponse) => "WaniKani is having trouble",
+            AppError::Template(_) | AppError::Export(_) => "Something went wrong",
+            AppError::InvalidCsrfToken => "This form has expired",
+        }
+    }
+
+    /// The sentence shown to the user. This is separate from `Display` because the template error
+    /// itself is only useful to us, not to whoever is looking at the page.
+    pub fn message(&self) -> String {
+        match self {
+            AppError::WaniKani(WaniKaniError::InvalidToken) => {
+                "WaniKani no longer accepts the API token you logged in with. It may have been deleted or regenerated, so log in again with a current one.".to_string()
+            }
+            AppError::WaniKani(WaniKaniError::MissingReadAccess(endpoint)) => format!(
+                "The API token you logged in with is not allowed to read your {}. Log in again with a token that can.",
+                endpoint
+            ),
+            AppError::WaniKani(WaniKaniError::RateLimited) => {
+                "WaniKani is rate limiting us right now. Give it a minute and try again.".to_string()
+            }
+            AppError::WaniKani(WaniKaniError::Unavailable) => {
+                "We couldn't reach WaniKani. It may be down for maintenance, so try again in a few minutes.".to_string()
+            }
+            AppError::WaniKani(WaniKaniError::UnexpectedResponse) => {
+                "WaniKani sent back data we didn't understand. Try again in a few minutes.".to_string()
+            }
+            AppError::Template(_) => {
+                "Something went wrong on our end while putting the page together. Try again in a moment.".to_string()
+            }
+            AppError::Export(_) => {
+                "Something went wrong on our end while putting your export together. Try again in a moment.".to_string()
+            }
+            AppError::InvalidCsrfToken => {
+                "The page you sent that from is out of date, or didn't come from this site. Reload it and try again.".to_string()
+            }
+        }
+    }
+
+    fn next_step(&self) -> (&'static str, &'static str) {
+        match self {
+            AppError::WaniKani(WaniKaniError::InvalidToken)
+            | AppError::WaniKani(WaniKaniError::MissingReadAccess(_)) => ("/login", "Log in again"),
+            AppError::InvalidCsrfToken => ("/", "Start over"),
+            _ => ("/info", "Try again"),
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AppError::WaniKani(err) => write!(f, "{}", err),
+            AppError::Template(err) => write!(f, "{}", err),
+            AppError::Export(err) => write!(f, "{}", err),
+            AppError::InvalidCsrfToken => write!(f, "missing or mismatched CSRF token"),
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
+
+impl From<WaniKaniError> for AppError {
+    fn from(err: WaniKaniError) -> Self {
+        AppError::WaniKani(err)
+    }
+}
+
+impl From<minijinja::Error> for AppError {
+    fn from(err: minijinja::Error) -> Self {
+        AppError::Template(err)
+    }
+}
+
+impl From<csv::Error> for AppError {
+    fn from(err: csv::Error) -> Self {
+        AppError::Export(err)
+    }
+}
+
+/// The error page gets its own little environment instead of borrowing the one in `AppState`. `IntoResponse`
+/// has no way to reach the state, and it means a broken template elsewhere can't also break the page that
+/// reports it.
+fn error_env() -> &'static Environment<'static> {
+    static ENV: OnceLock<Environment<'static>> = OnceLock::new();
+
+    ENV.get_or_init(|| {
+        let mut env = Environment::new();
+
+        env.add_template("base", include_str!("../templates/base.jinja"))
+            .unwrap();
+
+        env.add_template("error", include_str!("../templates/error.jinja"))
+            .unwrap();
+
+        env
+    })
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        if self.status().is_server_error() {
+            tracing::error!(error = %self, "request failed");
+        } else {
+            tracing::warn!(error = %self, "request failed");
+        }
+
+        let (next_url, next_label) = self.next_step();
+        let rendered = error_env().get_template("error").and_then(|template| {
+            template.render(context! {
+                title => self.title(),
+                message => self.message(),
+                next_url => next_url,
+                next_label => next_label,
+            })
+        });
+
+        match rendered {
+            Ok(rendered) => (self.status(), Html(rendered)).into_response(),
+            Err(_) => (self.status(), self.message()).into_response(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn test_wanikani_failures_get_their_own_status() {
+        assert_eq!(
+            AppError::from(WaniKaniError::InvalidToken).status(),
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            AppError::from(WaniKaniError::RateLimited).status(),
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        assert_eq!(
+            AppError::from(WaniKaniError::Unavailable).status(),
+            StatusCode::BAD_GATEWAY
+        );
+    }
+
+    #[test]
+    fn test_wanikani_failures_get_their_own_message() {
+        let bad_token = AppError::from(WaniKaniError::InvalidToken).message();
+        let rate_limited = AppError::from(WaniKaniError::RateLimited).message();
+        let down = AppError::from(WaniKaniError::Unavailable).message();
+
+        assert_ne!(bad_token, rate_limited);
+        assert_ne!(rate_limited, down);
+        assert_ne!(bad_token, down);
+    }
+
+    #[test]
+    fn test_error_page_renders_message() {
+        let err = AppError::from(WaniKaniError::RateLimited);
+        let message = err.message();
+        let (next_url, next_label) = err.next_step();
+
+        let rendered = error_env()
+            .get_template("error")
+            .unwrap()
+            .render(context! {
+                title => err.title(),
+                message => message,
+                next_url => next_url,
+                next_label => next_label,
+            })
+            .unwrap();
+
+        assert!(rendered.contains(&err.message()));
+    }
+}
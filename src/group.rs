@@ -0,0 +1,430 @@
+use crate::{UserToken, LONG_IDLE_TIMEOUT};
+use chrono::{DateTime, Duration, Local};
+use moka::future::Cache;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use uuid::Uuid;
+use wanikani_stats::data_processing::{
+    report::{items_passed_since, level_ups},
+    CompleteUserInfo,
+};
+
+/// A leaderboard is for a study group, not a whole forum, so groups stop taking members here.
+pub const MAX_MEMBERS: usize = 50;
+
+/// Nobody needs more than a handful of study groups, and this keeps one user from filling up the store.
+pub const MAX_OWNED_GROUPS: usize = 10;
+
+/// What a group knows about one member, worked out from their `CompleteUserInfo` the last time it was in
+/// the cache. Other members' data is never fetched just to show the leaderboard, so for anyone who hasn't
+/// been around lately this is all there is, and `as_of` says how old it is.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemberStats {
+    pub username: String,
+    pub level: i32,
+    pub passed_this_week: i32,
+    /// Empty for someone who hasn't done any reviews yet.
+    pub accuracy: Option<f64>,
+    /// Empty if WaniKani had no level progressions for them.
+    pub days_on_level: Option<f64>,
+    pub as_of: DateTime<Local>,
+}
+
+impl MemberStats {
+    pub fn new(info: &CompleteUserInfo) -> Self {
+        let as_of = *info.get_fetched_at();
+
+        MemberStats {
+            username: info.get_user_name().to_string(),
+            level: info.get_level(),
+            passed_this_week: items_passed_since(info, as_of - Duration::days(7)),
+            accuracy: info.stats().accuracy().percentage(),
+            days_on_level: level_ups(info, as_of)
+                .last()
+                .filter(|level_up| level_up.passed_at.is_none())
+                .map(|level_up| level_up.days),
+            as_of,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Group {
+    pub name: String,
+    pub owner: UserToken,
+    /// In the order they joined, starting with the owner.
+    pub members: Vec<UserToken>,
+    /// The code in the invite link. Resetting it stops old links from working.
+    pub invite: String,
+    /// The last time anyone looked at the group or changed it. A group left alone for `LONG_IDLE_TIMEOUT`
+    /// is deleted to make room for new ones.
+    pub last_active: DateTime<Local>,
+}
+
+impl Group {
+    fn is_idle(&self, now: DateTime<Local>) -> bool {
+        (now - self.last_active).to_std().unwrap_or_default() >= LONG_IDLE_TIMEOUT
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum GroupError {
+    NotFound,
+    BadInvite,
+    Full,
+    NotOwner,
+    TooManyOwned,
+    NoRoom,
+}
+
+impl GroupError {
+    /// What the user is told, as a flash message.
+    pub fn message(&self) -> &'static str {
+        match self {
+            GroupError::NotFound => "That group doesn't exist anymore",
+            GroupError::BadInvite => "That invite link has been replaced, ask for a new one",
+            GroupError::Full => "That group is full",
+            GroupError::NotOwner => "Only the group's owner can do that",
+            GroupError::TooManyOwned => {
+                "You already own as many groups as you can, leave one first"
+            }
+            GroupError::NoRoom => "We can't take any more groups right now, try again later",
+        }
+    }
+}
+
+/// Every group and the last known stats of everyone in one. Membership goes by the token someone logged in
+/// with, the same as sessions. Groups change in several steps at once (checking the invite, checking the
+/// size, adding the member) so they sit behind a lock rather than in a moka cache. A group is deleted when its
+/// last member leaves or once it has sat idle for `LONG_IDLE_TIMEOUT`, and there are never more than
+/// `capacity` of them. Saved stats go as soon as their member is in no group at all, or once nobody has
+/// looked at them for `LONG_IDLE_TIMEOUT`.
+#[derive(Clone)]
+pub struct GroupStore {
+    groups: Arc<RwLock<HashMap<String, Group>>>,
+    member_stats: Cache<UserToken, MemberStats>,
+    capacity: usize,
+}
+
+impl GroupStore {
+    pub fn new(capacity: u64) -> Self {
+        GroupStore {
+            groups: Arc::new(RwLock::new(HashMap::new())),
+            member_stats: Cache::builder()
+                .max_capacity(capacity)
+                .time_to_idle(LONG_IDLE_TIMEOUT)
+                .build(),
+            capacity: capacity as usize,
+        }
+    }
+
+    /// Starts a group with `owner` as its only member and returns its id. Idle groups are cleared out first,
+    /// since this is the only way the store grows.
+    pub async fn create(&self, name: &str, owner: &UserToken) -> Result<String, GroupError> {
+        let now = Local::now();
+        let mut groups = self.groups.write().await;
+        let idle: Vec<String> = groups
+            .iter()
+            .filter(|(_, group)| group.is_idle(now))
+            .map(|(id, _)| id.clone())
+            .collect();
+
+        for id in idle {
+            self.remove_group(&mut groups, &id).await;
+        }
+
+        if groups
+            .values()
+            .filter(|group| group.owner == *owner)
+            .count()
+            >= MAX_OWNED_GROUPS
+        {
+            return Err(GroupError::TooManyOwned);
+        }
+
+        if groups.len() >= self.capacity {
+            return Err(GroupError::NoRoom);
+        }
+
+        let id = Uuid::new_v4().simple().to_string();
+        let group = Group {
+            name: name.trim().to_string(),
+            owner: owner.clone(),
+            members: vec![owner.clone()],
+            invite: Uuid::new_v4().simple().to_string(),
+            last_active: now,
+        };
+
+        groups.insert(id.clone(), group);
+
+        Ok(id)
+    }
+
+    /// The group, which counts as it being used. One that has gone idle is treated as already gone.
+    pub async fn get(&self, id: &str) -> Option<Group> {
+        let mut groups = self.groups.write().await;
+        let group = self.live_group(&mut groups, id).await.ok()?;
+
+        group.last_active = Local::now();
+
+        Some(group.clone())
+    }
+
+    /// Looks up a group for anything that uses or changes it. A group that has gone idle is deleted on the
+    /// spot instead, so nothing done to it can bring it back.
+    async fn live_group<'g>(
+        &self,
+        groups: &'g mut HashMap<String, Group>,
+        id: &str,
+    ) -> Result<&'g mut Group, GroupError> {
+        if groups
+            .get(id)
+            .is_some_and(|group| group.is_idle(Local::now()))
+        {
+            self.remove_group(groups, id).await;
+        }
+
+        groups.get_mut(id).ok_or(GroupError::NotFound)
+    }
+
+    /// Deletes a group, along with the saved stats of any member who isn't in another group.
+    async fn remove_group(&self, groups: &mut HashMap<String, Group>, id: &str) {
+        let Some(group) = groups.remove(id) else {
+            return;
+        };
+
+        for member in &group.members {
+            self.forget_if_groupless(groups, member).await;
+        }
+    }
+
+    async fn forget_if_groupless(&self, groups: &HashMap<String, Group>, token: &UserToken) {
+        if !groups.values().any(|group| group.members.contains(token)) {
+            self.member_stats.invalidate(token).await;
+        }
+    }
+
+    /// Every group `token` is in, by name.
+    pub async fn for_member(&self, token: &UserToken) -> Vec<(String, Group)> {
+        let mut groups: Vec<(String, Group)> = self
+            .groups
+            .read()
+            .await
+            .iter()
+            .filter(|(_, group)| group.members.contains(token) && !group.is_idle(Local::now()))
+            .map(|(id, group)| (id.clone(), group.clone()))
+            .collect();
+
+        groups.sort_by(|(_, a), (_, b)| a.name.cmp(&b.name));
+
+        groups
+    }
+
+    /// Adds `token` to the group if `invite` is its current invite code. Joining a group you are already
+    /// in does nothing.
+    pub async fn join(&self, id: &str, invite: &str, token: &UserToken) -> Result<(), GroupError> {
+        let mut groups = self.groups.write().await;
+        let group = self.live_group(&mut groups, id).await?;
+
+        if group.invite != invite {
+            return Err(GroupError::BadInvite);
+        }
+
+        group.last_active = Local::now();
+
+        if group.members.contains(token) {
+            return Ok(());
+        }
+
+        if group.members.len() >= MAX_MEMBERS {
+            return Err(GroupError::Full);
+        }
+
+        group.members.push(token.clone());
+
+        Ok(())
+    }
+
+    /// Takes `token` out of the group. An owner who leaves hands the group to whoever has been in it the
+    /// longest, and the last one out deletes it. Their saved stats go too once they are in no group at all.
+    pub async fn leave(&self, id: &str, token: &UserToken) {
+        let mut groups = self.groups.write().await;
+        let Ok(group) = self.live_group(&mut groups, id).await else {
+            return;
+        };
+
+        group.members.retain(|member| member != token);
+        group.last_active = Local::now();
+
+        match group.members.first() {
+            Some(next) if group.owner == *token => group.owner = next.clone(),
+            Some(_) => {}
+            None => {
+                groups.remove(id);
+            }
+        }
+
+        self.forget_if_groupless(&groups, token).await;
+    }
+
+    /// Gives the group a new invite code, so links that have been passed around stop working.
+    pub async fn reset_invite(&self, id: &str, token: &UserToken) -> Result<(), GroupError> {
+        let mut groups = self.groups.write().await;
+        let group = self.live_group(&mut groups, id).await?;
+
+        if group.owner != *token {
+            return Err(GroupError::NotOwner);
+        }
+
+        group.invite = Uuid::new_v4().simple().to_string();
+        group.last_active = Local::now();
+
+        Ok(())
+    }
+
+    pub async fn record_stats(&self, token: &UserToken, stats: MemberStats) {
+        self.member_stats.insert(token.clone(), stats).await;
+    }
+
+    pub async fn member_stats(&self, token: &UserToken) -> Option<MemberStats> {
+        self.member_stats.get(token).await
+    }
+
+    /// Settles the saved member stats, for `AppState::flush_stores`. The groups themselves are a plain map
+    /// with nothing pending.
+    pub async fn flush(&self) {
+        self.member_stats.run_pending_tasks().await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    fn token(token: &str) -> UserToken {
+        UserToken {
+            token: token.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_joining_needs_the_current_invite() {
+        let store = GroupStore::new(10);
+        let id = store.create("Study Group", &token("owner")).await.unwrap();
+        let invite = store.get(&id).await.unwrap().invite;
+
+        assert_eq!(
+            store.join(&id, "guess", &token("member")).await,
+            Err(GroupError::BadInvite)
+        );
+        assert_eq!(store.join(&id, &invite, &token("member")).await, Ok(()));
+        assert_eq!(store.join(&id, &invite, &token("member")).await, Ok(()));
+        assert_eq!(store.get(&id).await.unwrap().members.len(), 2);
+
+        store.reset_invite(&id, &token("owner")).await.unwrap();
+
+        assert_eq!(
+            store.join(&id, &invite, &token("late")).await,
+            Err(GroupError::BadInvite)
+        );
+        assert_eq!(
+            store.reset_invite(&id, &token("member")).await,
+            Err(GroupError::NotOwner)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_owner_leaving_hands_over_and_last_out_deletes() {
+        let store = GroupStore::new(10);
+        let id = store.create("Study Group", &token("owner")).await.unwrap();
+        let invite = store.get(&id).await.unwrap().invite;
+        store.join(&id, &invite, &token("member")).await.unwrap();
+
+        store.leave(&id, &token("owner")).await;
+
+        assert_eq!(store.get(&id).await.unwrap().owner, token("member"));
+
+        store.leave(&id, &token("member")).await;
+
+        assert_eq!(store.get(&id).await, None);
+        assert!(store.for_member(&token("member")).await.is_empty());
+    }
+
+    fn stats(username: &str) -> MemberStats {
+        MemberStats {
+            username: username.to_string(),
+            level: 1,
+            passed_this_week: 0,
+            accuracy: None,
+            days_on_level: None,
+            as_of: Local::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stats_are_forgotten_once_in_no_group() {
+        let store = GroupStore::new(10);
+        let first = store.create("First", &token("owner")).await.unwrap();
+        let second = store.create("Second", &token("owner")).await.unwrap();
+        store.record_stats(&token("owner"), stats("owner")).await;
+
+        store.leave(&first, &token("owner")).await;
+
+        assert!(store.member_stats(&token("owner")).await.is_some());
+
+        store.leave(&second, &token("owner")).await;
+
+        assert_eq!(store.member_stats(&token("owner")).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_idle_group_cannot_be_revived() {
+        let store = GroupStore::new(10);
+        let id = store.create("Study Group", &token("owner")).await.unwrap();
+        store.record_stats(&token("owner"), stats("owner")).await;
+        store.groups.write().await.get_mut(&id).unwrap().last_active =
+            Local::now() - Duration::days(91);
+
+        assert_eq!(
+            store.reset_invite(&id, &token("owner")).await,
+            Err(GroupError::NotFound)
+        );
+        assert!(store.groups.read().await.is_empty());
+        assert_eq!(store.member_stats(&token("owner")).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_groups_are_limited_and_idle_ones_make_room() {
+        let store = GroupStore::new(MAX_OWNED_GROUPS as u64 + 1);
+        let mut ids = Vec::new();
+
+        for _ in 0..MAX_OWNED_GROUPS {
+            ids.push(store.create("Study Group", &token("owner")).await.unwrap());
+        }
+
+        assert_eq!(
+            store.create("One More", &token("owner")).await,
+            Err(GroupError::TooManyOwned)
+        );
+
+        store.create("Study Group", &token("other")).await.unwrap();
+
+        assert_eq!(
+            store.create("Study Group", &token("third")).await,
+            Err(GroupError::NoRoom)
+        );
+
+        store
+            .groups
+            .write()
+            .await
+            .get_mut(&ids[0])
+            .unwrap()
+            .last_active = Local::now() - Duration::days(91);
+
+        assert_eq!(store.get(&ids[0]).await, None);
+        assert!(store.create("Study Group", &token("third")).await.is_ok());
+    }
+}
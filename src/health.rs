@@ -0,0 +1,85 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// If WaniKani answered anyone within this long, /readyz takes that as proof it is reachable
+/// instead of asking it again on every probe.
+pub const RECENT_CONTACT: Duration = Duration::from_secs(300);
+
+/// How long /readyz waits on WaniKani before calling it unreachable. Orchestrators usually give up
+/// on a probe after a few seconds, so there is no point waiting any longer.
+pub const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// One thing /readyz checks. `detail` is for whoever is reading the response when something is
+/// wrong, and isn't meant to be parsed.
+#[derive(Debug, Serialize)]
+pub struct ComponentStatus {
+    pub ready: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl ComponentStatus {
+    pub fn ready(detail: impl Into<Option<String>>) -> Self {
+        ComponentStatus {
+            ready: true,
+            detail: detail.into(),
+        }
+    }
+
+    pub fn unavailable(detail: String) -> Self {
+        ComponentStatus {
+            ready: false,
+            detail: Some(detail),
+        }
+    }
+}
+
+/// The JSON body of /readyz. The server is ready only when every component is.
+#[derive(Debug, Serialize)]
+pub struct Readiness {
+    pub ready: bool,
+    pub components: BTreeMap<&'static str, ComponentStatus>,
+}
+
+impl Readiness {
+    pub fn new(components: BTreeMap<&'static str, ComponentStatus>) -> Self {
+        Readiness {
+            ready: components.values().all(|component| component.ready),
+            components,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn test_one_unavailable_component_makes_server_unready() {
+        let readiness = Readiness::new(BTreeMap::from([
+            ("templates", ComponentStatus::ready(None)),
+            (
+                "wanikani",
+                ComponentStatus::unavailable("connection refused".to_string()),
+            ),
+        ]));
+
+        assert!(!readiness.ready);
+        assert_eq!(
+            serde_json::to_value(&readiness).unwrap()["components"]["wanikani"]["detail"],
+            "connection refused"
+        );
+    }
+
+    #[test]
+    fn test_all_components_ready() {
+        let readiness = Readiness::new(BTreeMap::from([
+            ("templates", ComponentStatus::ready(None)),
+            ("sessions", ComponentStatus::ready("3 live".to_string())),
+        ]));
+
+        assert!(readiness.ready);
+    }
+}
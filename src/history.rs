@@ -0,0 +1,144 @@
+use crate::{UserToken, LONG_IDLE_TIMEOUT};
+use chrono::NaiveDate;
+use moka::future::Cache;
+use moka::ops::compute::Op;
+use std::collections::BTreeMap;
+use std::mem::size_of;
+use std::sync::Arc;
+use wanikani_stats::data_processing::{
+    snapshot::{Snapshot, SubjectState},
+    Accuracy, CompleteUserInfo,
+};
+
+/// How many days of snapshots are kept for each user. Comparisons are mostly week to week, so this is
+/// plenty, and it caps how much memory a single user can take up.
+pub const MAX_SNAPSHOTS: usize = 90;
+
+/// Roughly how much memory every user's history put together is given. Someone near the end of WaniKani has
+/// around 9000 subjects in each snapshot, so a full 90 days of theirs comes to about 10MB, and the users
+/// who have been around least recently are dropped first once this fills up.
+pub const MAX_HISTORY_BYTES: u64 = 512 * 1024 * 1024;
+
+type History = Arc<BTreeMap<NaiveDate, Arc<Snapshot>>>;
+
+/// A snapshot of each user's data for every day it was fetched, so that /compare has something to compare.
+/// Only the last snapshot of a day is kept, for at most `MAX_SNAPSHOTS` days. A user whose data hasn't been
+/// fetched for `LONG_IDLE_TIMEOUT` loses their history. A user's history is never changed in place, only
+/// replaced, so the cache always knows how big it is.
+#[derive(Clone)]
+pub struct HistoryStore {
+    histories: Cache<UserToken, History>,
+}
+
+impl HistoryStore {
+    /// `max_bytes` is how much memory every user's history put together gets, going by `approximate_size`.
+    pub fn new(max_bytes: u64) -> Self {
+        HistoryStore {
+            histories: Cache::builder()
+                .max_capacity(max_bytes)
+                .weigher(|_, history: &History| {
+                    history
+                        .values()
+                        .map(|snapshot| approximate_size(snapshot))
+                        .sum::<usize>()
+                        .try_into()
+                        .unwrap_or(u32::MAX)
+                })
+                .time_to_idle(LONG_IDLE_TIMEOUT)
+                .build(),
+        }
+    }
+
+    /// Takes a snapshot of data that was just fetched, replacing any snapshot from earlier the same day.
+    /// Only a fetch that actually went to WaniKani should call this, not a read from the cache.
+    pub async fn record(&self, token: &UserToken, info: &CompleteUserInfo) {
+        self.insert(token, Snapshot::new(info)).await;
+    }
+
+    async fn insert(&self, token: &UserToken, snapshot: Snapshot) {
+        // updates for the same user are run one after another, so two fetches finishing together can't
+        // each replace the history without the other's snapshot
+        self.histories
+            .entry(token.clone())
+            .and_compute_with(|previous| {
+                let mut history = previous
+                    .map(|previous| BTreeMap::clone(&previous.into_value()))
+                    .unwrap_or_default();
+
+                history.insert(snapshot.taken_at.date_naive(), Arc::new(snapshot));
+
+                while history.len() > MAX_SNAPSHOTS {
+                    history.pop_first();
+                }
+
+                std::future::ready(Op::Put(Arc::new(history)))
+            })
+            .await;
+    }
+
+    /// Every day the user has a snapshot for, oldest first.
+    pub async fn dates(&self, token: &UserToken) -> Vec<NaiveDate> {
+        match self.histories.get(token).await {
+            Some(history) => history.keys().copied().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// The latest snapshot from `date` or before. If history doesn't go back that far, the oldest snapshot
+    /// there is stands in for it.
+    pub async fn on_or_before(&self, token: &UserToken, date: NaiveDate) -> Option<Arc<Snapshot>> {
+        let history = self.histories.get(token).await?;
+
+        history
+            .range(..=date)
+            .next_back()
+            .or_else(|| history.first_key_value())
+            .map(|(_, snapshot)| snapshot.clone())
+    }
+
+    /// Settles every user's history, for `AppState::flush_stores`.
+    pub async fn flush(&self) {
+        self.histories.run_pending_tasks().await;
+    }
+}
+
+/// What a snapshot takes up, near enough. The subjects are nearly all of it.
+fn approximate_size(snapshot: &Snapshot) -> usize {
+    size_of::<Snapshot>()
+        + snapshot.subjects.capacity() * size_of::<(i32, SubjectState)>()
+        + snapshot.accuracy.capacity() * size_of::<(&str, Accuracy)>()
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use chrono::{Local, TimeZone};
+
+    fn snapshot(day: u32) -> Snapshot {
+        Snapshot {
+            taken_at: Local.with_ymd_and_hms(2024, 5, day, 12, 0, 0).unwrap(),
+            level: day as i32,
+            subjects: Vec::new(),
+            accuracy: vec![],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dates_without_a_snapshot_use_the_one_before() {
+        let store = HistoryStore::new(MAX_HISTORY_BYTES);
+        let token = UserToken {
+            token: "token".to_string(),
+        };
+        let day = |day| NaiveDate::from_ymd_opt(2024, 5, day).unwrap();
+
+        store.insert(&token, snapshot(3)).await;
+        store.insert(&token, snapshot(10)).await;
+
+        assert_eq!(store.on_or_before(&token, day(9)).await.unwrap().level, 3);
+        assert_eq!(store.on_or_before(&token, day(20)).await.unwrap().level, 10);
+        // nothing goes back that far, so the oldest there is will have to do
+        assert_eq!(store.on_or_before(&token, day(1)).await.unwrap().level, 3);
+        assert_eq!(store.dates(&token).await, [day(3), day(10)]);
+    }
+}
@@ -1,205 +1,1780 @@
+mod calendar;
+mod config;
+mod csrf;
+mod error;
+mod group;
+mod health;
+mod history;
+mod profile;
+mod session;
+mod telemetry;
+
 use axum::{
-    extract::State,
-    http::StatusCode,
-    response::{Html, IntoResponse, Redirect, Response},
-    routing::get,
-    Form, Router,
+    extract::{FromRef, Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    middleware::{from_fn, from_fn_with_state},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        Html, IntoResponse, Redirect, Response,
+    },
+    routing::{get, post},
+    Extension, Form, Json, Router,
 };
-use axum_extra::extract::cookie::{Cookie, CookieJar};
+use axum_extra::extract::cookie::{Cookie, Key, PrivateCookieJar};
+use calendar::CalendarStore;
+use config::{Config, SessionConfig};
+use csrf::{verify_csrf, CsrfToken};
+use error::AppError;
+use futures_util::stream::{self, Stream, StreamExt};
 use governor::{Quota, RateLimiter};
+use group::{GroupStore, MemberStats};
+use health::{ComponentStatus, Readiness};
+use history::HistoryStore;
+use metrics_exporter_prometheus::PrometheusHandle;
 use minijinja::{context, Environment};
 use moka::future::Cache;
-use nonzero_ext::*;
+use profile::{ProfileFields, ProfileStore, SavedPage};
 use serde::Deserialize;
-use std::time::Duration;
-use std::{collections::HashMap, sync::Arc};
-use tokio::sync::RwLock;
-use uuid::Uuid;
-use wanikani_stats::data_processing::{ApiClient, CompleteUserInfo};
+use session::{removal_cookie, session_cookie, site_cookie, site_removal_cookie, SessionStore};
+use std::convert::Infallible;
+use std::fmt;
+use std::num::NonZeroU32;
+use std::ops::Bound;
+use std::time::Instant;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
+use tokio::sync::{watch, RwLock};
+use tower_http::services::ServeDir;
+use tower_http::trace::{DefaultOnResponse, TraceLayer};
+use tracing::Level;
+use tracing_subscriber::EnvFilter;
+use wanikani_stats::data_processing::{
+    anki::{anki_notes, write_tsv, DeckFilter},
+    report::{accuracy_table, item_rows, level_times, level_ups, reset_history, reset_progress},
+    snapshot::compare,
+    ApiClient, ApiContact, CompleteUserInfo, FetchProgress, Question, SubjectType, WaniKaniError,
+};
+
+/// Every template the server renders. /readyz checks that each of these made it into the environment.
+const TEMPLATE_NAMES: [&str; 10] = [
+    "base",
+    "login",
+    "info",
+    "info_stats",
+    "settings",
+    "profile",
+    "groups",
+    "group",
+    "group_join",
+    "compare",
+];
 
-#[derive(Debug, Hash, PartialEq, Eq, Clone)]
+/// How long calendar feeds, public profiles and snapshot history are kept without anybody touching them. These are things a user sets up
+/// once and then leaves alone, and a calendar app only comes back every few hours at best, so they outlast a session by a long way.
+const LONG_IDLE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(90 * 24 * 60 * 60);
+
+#[derive(Hash, PartialEq, Eq, Clone)]
 struct UserToken {
     token: String,
 }
 
+/// Tokens show up in a lot of places, so this keeps them out of anything that gets logged.
+impl fmt::Debug for UserToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("UserToken([redacted])")
+    }
+}
+
 #[derive(Deserialize)]
 struct TokenForm {
     wk_token: String,
 }
 
+#[derive(Deserialize)]
+struct CalendarForm {
+    threshold: i32,
+}
+
+#[derive(Deserialize)]
+struct AccuracyQuery {
+    types: Option<String>,
+    min_level: Option<i32>,
+    max_level: Option<i32>,
+    question: Option<Question>,
+}
+
+/// Dates are `YYYY-MM-DD`, which is what a date input sends.
+#[derive(Deserialize)]
+struct CompareQuery {
+    from: Option<chrono::NaiveDate>,
+    to: Option<chrono::NaiveDate>,
+}
+
+#[derive(Deserialize)]
+struct GroupForm {
+    name: String,
+}
+
+/// Unticked checkboxes aren't sent at all, hence the defaults.
+#[derive(Deserialize)]
+struct ProfileForm {
+    slug: String,
+    #[serde(default)]
+    level: bool,
+    #[serde(default)]
+    learned: bool,
+    #[serde(default)]
+    accuracy: bool,
+    #[serde(default)]
+    level_ups: bool,
+}
+
 /// AppState
-/// 
-/// When I got to this point, I started to realize some of my limited understanding of Backend development. I am not sure if this is the best way to handle storing user information to 
-/// prevent the user from having to login every time they visit the site. The cache seems fine but I'd like for there to be a way for the user to force a refresh of their data. I am also relatively
+///
+/// When I got to this point, I started to realize some of my limited understanding of Backend development. I am not sure if this is the best way to handle storing user information to
+/// prevent the user from having to login every time they visit the site. The cache seems fine, and users can force a refresh of their data through /refresh, which `refresh_cooldowns` keeps from being spammed. I am also relatively
 /// new to understanding the implications of async programming so I don't know if I am using ARC and RwLock correctly.
 #[derive(Clone)]
 struct AppState {
-    cookie_to_token: Arc<RwLock<HashMap<String, UserToken>>>,
+    sessions: SessionStore,
+    calendars: CalendarStore,
+    profiles: ProfileStore,
+    groups: GroupStore,
+    history: HistoryStore,
+    cookie_key: Key,
     user_info_cache: Cache<UserToken, CompleteUserInfo>,
+    refresh_cooldowns: Cache<UserToken, Instant>,
+    /// Public profiles whose owner had a background load started for them by a visitor, so that happens at most once per TTL.
+    profile_loads: Cache<UserToken, ()>,
+    recently_active: Cache<UserToken, ()>,
+    loading: Arc<RwLock<HashMap<UserToken, watch::Receiver<FetchProgress>>>>,
+    load_failures: Cache<UserToken, WaniKaniError>,
     rate_limiter: Arc<governor::DefaultDirectRateLimiter>,
     reqwest_client: reqwest::Client,
+    api_contact: Arc<ApiContact>,
     env: Environment<'static>,
+    config: Arc<Config>,
+    metrics: PrometheusHandle,
+    shutdown: watch::Receiver<bool>,
 }
 
 impl AppState {
-    async fn get_or_cache_user_data(&self, token: &UserToken) -> Option<CompleteUserInfo> {
-        let rate_limiter = self.rate_limiter.clone();
-        let user_info_cache = self.user_info_cache.clone();
-        let reqwest_client = self.reqwest_client.clone();
-        let token = token.clone();
-        let user_info = user_info_cache
-            .get_with(token.clone(), async move {
-                let rate_limiter = rate_limiter.clone();
-                let reqwest_client = reqwest_client.clone();
-                let token = token.clone();
-                let api_client = ApiClient::new(token.token, &reqwest_client, &rate_limiter);
-                api_client.build_complete_user_info().await.unwrap()
-            })
+    fn api_client(&self, token: &UserToken) -> ApiClient<'_> {
+        ApiClient::new(
+            token.token.clone(),
+            &self.reqwest_client,
+            &self.rate_limiter,
+        )
+        .with_base_url(&self.config.wanikani_base_url)
+        .with_contact(&self.api_contact)
+    }
+
+    /// WaniKani counts as reachable if it answered anyone recently. If it hasn't, because nobody has been using the site, it is asked
+    /// directly. The base url on its own needs no token and WaniKani answers it with a 404, but any answer at all means it is up.
+    async fn wanikani_status(&self) -> ComponentStatus {
+        if let Some(last_response) = self.api_contact.last_response() {
+            if last_response.elapsed() < health::RECENT_CONTACT {
+                return ComponentStatus::ready(format!(
+                    "last answered {} seconds ago",
+                    last_response.elapsed().as_secs()
+                ));
+            }
+        }
+
+        let probe = self
+            .reqwest_client
+            .get(&self.config.wanikani_base_url)
+            .timeout(health::PROBE_TIMEOUT)
+            .send()
             .await;
 
-        Some(user_info)
+        match probe {
+            Ok(response) if !response.status().is_server_error() => {
+                self.api_contact.record_response();
+
+                ComponentStatus::ready("answered a probe just now".to_string())
+            }
+            Ok(response) => {
+                ComponentStatus::unavailable(format!("answered with {}", response.status()))
+            }
+            Err(err) => ComponentStatus::unavailable(err.to_string()),
+        }
+    }
+
+    /// Gets the user's data out of the cache, fetching it from WaniKani first if it isn't there. Concurrent callers for the same token
+    /// share a single fetch, and a failed fetch leaves nothing behind in the cache so the next call tries again.
+    async fn get_or_cache_user_data(
+        &self,
+        token: &UserToken,
+    ) -> Result<CompleteUserInfo, WaniKaniError> {
+        self.recently_active.insert(token.clone(), ()).await;
+
+        if let Some(user_info) = self.user_info_cache.get(token).await {
+            telemetry::record_cache_lookup(true);
+
+            return Ok(user_info);
+        }
+
+        telemetry::record_cache_lookup(false);
+
+        let api_client = self.api_client(token);
+
+        self.user_info_cache
+            .try_get_with(token.clone(), self.fetch_user_data(token, api_client))
+            .await
+            .map_err(|err| (*err).clone())
+    }
+
+    /// Fetches all of the user's data and takes the day's snapshot of it. This is what goes into `try_get_with`, so a snapshot is only
+    /// taken when a fetch really went to WaniKani, once, and never for a read from the cache or for a caller that joined someone else's fetch.
+    async fn fetch_user_data(
+        &self,
+        token: &UserToken,
+        api_client: ApiClient<'_>,
+    ) -> Result<CompleteUserInfo, WaniKaniError> {
+        let user_info = api_client.build_complete_user_info().await?;
+        self.history.record(token, &user_info).await;
+
+        Ok(user_info)
+    }
+
+    /// Starts fetching the user's data in the background, or joins the fetch that is already running for them, and returns a receiver
+    /// that sees every page the fetch makes it through. The sending half is dropped once the fetch is over, successful or not, which
+    /// is how whoever is watching knows to go look in the cache.
+    async fn start_loading(&self, token: &UserToken) -> watch::Receiver<FetchProgress> {
+        let mut loading = self.loading.write().await;
+
+        if let Some(progress) = loading.get(token) {
+            return progress.clone();
+        }
+
+        telemetry::record_cache_lookup(false);
+
+        let (progress_sender, progress) = watch::channel(FetchProgress::default());
+        loading.insert(token.clone(), progress.clone());
+        self.load_failures.invalidate(token).await;
+
+        let state = self.clone();
+        let token = token.clone();
+
+        tokio::spawn(async move {
+            state.recently_active.insert(token.clone(), ()).await;
+
+            let api_client = state.api_client(&token).with_progress(&progress_sender);
+
+            let result = state
+                .user_info_cache
+                .try_get_with(token.clone(), state.fetch_user_data(&token, api_client))
+                .await;
+
+            if let Err(err) = result {
+                tracing::warn!(error = %err, "loading user data failed");
+
+                state
+                    .load_failures
+                    .insert(token.clone(), (*err).clone())
+                    .await;
+            }
+
+            state.loading.write().await.remove(&token);
+        });
+
+        progress
+    }
+
+    /// Whether cached data is within the background refresh margin of its TTL.
+    fn expires_soon(&self, user_info: &CompleteUserInfo) -> bool {
+        let age = (chrono::Local::now() - user_info.get_fetched_at())
+            .to_std()
+            .unwrap_or_default();
+
+        let cache_config = &self.config.cache;
+
+        age >= cache_config
+            .user_info_ttl()
+            .saturating_sub(cache_config.background_refresh_margin())
+    }
+
+    /// Refreshes the user's cached data in place if it is close to expiring. Whatever is in the cache keeps being served while this runs, and if
+    /// the refresh fails the old data is left alone to expire on its own, at which point the next page view fetches everything again.
+    async fn refresh_if_expiring(&self, token: &UserToken) {
+        let Some(previous) = self.user_info_cache.get(token).await else {
+            return;
+        };
+
+        if !self.expires_soon(&previous) {
+            return;
+        }
+
+        let api_client = self.api_client(token);
+        match api_client.refresh_complete_user_info(&previous).await {
+            Ok(user_info) => {
+                self.history.record(token, &user_info).await;
+                self.user_info_cache.insert(token.clone(), user_info).await;
+            }
+            Err(err) => tracing::warn!(error = %err, "background refresh failed"),
+        }
+    }
+
+    /// Called once the drain period is over so nothing is lost to a shutdown. Every store only lives in memory for now, so all each one does
+    /// is let moka finish its pending work, but this is the one place anything would be written out if a store were ever kept anywhere else.
+    async fn flush_stores(&self) {
+        self.sessions.flush().await;
+        self.calendars.flush().await;
+        self.profiles.flush().await;
+        self.groups.flush().await;
+        self.history.flush().await;
+    }
+
+    /// Resolves once a shutdown has been asked for. Anything long running selects on this so the server isn't kept waiting on it.
+    async fn shutting_down(&self) {
+        let mut shutdown = self.shutdown.clone();
+
+        // the sender lives until main returns, so an error here means we are already on the way out
+        let _ = shutdown.wait_for(|shutting_down| *shutting_down).await;
+    }
+
+    /// Waits for every fetch that `start_loading` has going to finish. Each one drops its progress sender when it is done, which is
+    /// what ends the `changed` loop.
+    async fn wait_for_loading(&self) {
+        let loading: Vec<watch::Receiver<FetchProgress>> =
+            self.loading.read().await.values().cloned().collect();
+
+        for mut progress in loading {
+            while progress.changed().await.is_ok() {}
+        }
+    }
+
+    /// The start of any link that is meant to be used from outside the site, like a calendar feed. `public_url` from the config wins, and
+    /// otherwise the `Host` header of the request is trusted, which works fine but means setting `public_url` is worth it in production.
+    fn public_url(&self, headers: &HeaderMap) -> String {
+        if let Some(public_url) = &self.config.public_url {
+            return public_url.trim_end_matches('/').to_string();
+        }
+
+        let host = headers
+            .get(header::HOST)
+            .and_then(|host| host.to_str().ok())
+            .unwrap_or("localhost");
+        let scheme = if self.config.session.cookie_secure {
+            "https"
+        } else {
+            "http"
+        };
+
+        format!("{}://{}", scheme, host)
+    }
+
+    /// Looks up the token behind the session cookie, if the browser sent one and we still know about it.
+    async fn token_for_session(&self, jar: &PrivateCookieJar) -> Option<UserToken> {
+        let user_uuid = jar.get(&self.config.session.cookie_name)?;
+
+        self.sessions.get(user_uuid.value()).await
+    }
+}
+
+impl FromRef<AppState> for Key {
+    fn from_ref(state: &AppState) -> Key {
+        state.cookie_key.clone()
     }
 }
 
 /// /login POST
-/// 
-/// This is a pretty simple function that will accept some user input from the login form, generate a cookie, and then redirect the user to the /info page.
-/// The main problem is that validation will happen after the redirect, and I am not sure if that is the best way to handle this. You can give this a bogus
-/// API token, but the /info page will call the API, receive an error, and then redirect back to the /login page.
+///
+/// This accepts the token from the login form and asks WaniKani about it before anything else happens. The token has to belong to an account and be able to
+/// read every collection the /info page needs. If it can't, the login form is rendered again with a message that says whether the token itself was bad or
+/// whether WaniKani was just unreachable or rate limiting us, so the user knows if retrying will help. Only a token that passes gets a uuid cookie and a
+/// redirect to the /info page. The uuid is always a new one, and whatever session the browser had before is ended, so a uuid someone planted or copied
+/// before the login doesn't end up attached to this token.
 #[axum::debug_handler]
 async fn post_login(
-    jar: CookieJar,
+    jar: PrivateCookieJar,
     State(state): State<AppState>,
+    Extension(csrf): Extension<CsrfToken>,
     Form(wk_token_form): Form<TokenForm>,
-) -> Result<(CookieJar, Redirect), StatusCode> {
-    let user_uuid = Uuid::new_v4().to_string();
+) -> Result<Response, AppError> {
     let user_token = UserToken {
         token: wk_token_form.wk_token,
     };
-    state
-        .cookie_to_token
-        .write()
-        .await
-        .insert(user_uuid.clone(), user_token);
+    let api_client = state.api_client(&user_token);
+
+    if let Err(err) = api_client.validate_token().await {
+        let status = AppError::from(err.clone()).status();
+        let template = state.env.get_template("login")?;
+        let rendered =
+            template.render(context! { error => err.to_string(), csrf_token => csrf.0 })?;
+
+        return Ok((status, Html(rendered)).into_response());
+    }
+
+    let previous_uuid = jar
+        .get(&state.config.session.cookie_name)
+        .map(|cookie| cookie.value().to_string());
+    let user_uuid = state
+        .sessions
+        .rotate(previous_uuid.as_deref(), user_token)
+        .await;
+
     Ok((
-        jar.add(Cookie::new("user_uuid", user_uuid)),
+        jar.add(session_cookie(&state.config.session, user_uuid)),
         Redirect::to("/info"),
-    ))
+    )
+        .into_response())
 }
 
 /// /login GET
-/// 
+///
 /// This just presents the login form to the user. It first checks if the user has a cookie with a user_uuid. If they do, then they are redirected to the /info page.
-/// The /info page will check if the uuid cookie is valid and then display the user's information. If the user does not have a cookie, then they are presented with the login form.
-/// 
-async fn get_login(jar: CookieJar, State(state): State<AppState>) -> Response {
-    if jar.get("user_uuid").is_some() {
-        return Redirect::to("/info").into_response();
+/// The /info page will check if the uuid cookie is valid and then display the user's information. If the user does not have a cookie, then they are presented with the login form
+/// along with any flash message that was left for them, like the one /info leaves when it kicks someone back here.
+async fn get_login(
+    jar: PrivateCookieJar,
+    State(state): State<AppState>,
+    Extension(csrf): Extension<CsrfToken>,
+) -> Result<Response, AppError> {
+    if jar.get(&state.config.session.cookie_name).is_some() {
+        return Ok(Redirect::to("/info").into_response());
     }
 
-    let template = state.env.get_template("login").unwrap();
+    let (jar, flash) = take_flash(&state.config.session, jar);
+
+    let template = state.env.get_template("login")?;
 
-    let rendered = template.render(context! {}).unwrap();
+    let rendered = template.render(context! { flash => flash, csrf_token => csrf.0 })?;
 
-    Html(rendered).into_response()
+    Ok((jar, Html(rendered)).into_response())
+}
+
+/// /logout POST
+///
+/// Forgets the session on our end as well as in the browser. The uuid is removed from the session store first so that the old cookie is useless even if
+/// it was copied somewhere else, then the cookie is removed and the user is sent back to the login page with a note saying it worked.
+async fn post_logout(jar: PrivateCookieJar, State(state): State<AppState>) -> Response {
+    if let Some(user_uuid) = jar.get(&state.config.session.cookie_name) {
+        state.sessions.remove(user_uuid.value()).await;
+    }
+
+    let jar = jar.remove(removal_cookie(&state.config.session));
+
+    (
+        jar.add(flash_cookie(
+            &state.config.session,
+            "You have been logged out",
+        )),
+        Redirect::to("/login"),
+    )
+        .into_response()
+}
+
+/// Flash messages are one-shot notes stored in a cookie by whichever handler redirects, and read by whichever page the user lands on.
+/// This returns the message if there is one along with a jar that removes the cookie, so the message is only ever shown once.
+fn take_flash(config: &SessionConfig, jar: PrivateCookieJar) -> (PrivateCookieJar, Option<String>) {
+    match jar.get("flash") {
+        Some(cookie) => {
+            let message = cookie.value().to_string();
+
+            (
+                jar.remove(site_removal_cookie(config, "flash")),
+                Some(message),
+            )
+        }
+        None => (jar, None),
+    }
+}
+
+fn flash_cookie(config: &SessionConfig, message: &'static str) -> Cookie<'static> {
+    site_cookie(config, "flash", message)
+}
+
+/// Everything the stats part of the info page needs. This is shared by the full /info page and the partial that /refresh sends back,
+/// so both always show the same numbers.
+fn info_context(user_info: &CompleteUserInfo) -> minijinja::Value {
+    let started_date = user_info.get_started_at();
+    let current_date = chrono::Local::now();
+    let days_since_start = (current_date - started_date).num_days();
+    let reset_count = user_info.get_num_of_resets();
+    let progress = reset_progress(user_info);
+    let days_since_reset = (current_date - progress.since).num_days();
+    let resets: Vec<minijinja::Value> = reset_history(user_info)
+        .into_iter()
+        .map(|reset| {
+            context! {
+                date => reset.confirmed_at.format("%Y-%m-%d").to_string(),
+                original_level => reset.original_level,
+                target_level => reset.target_level,
+            }
+        })
+        .collect();
+    let accuracy: Vec<minijinja::Value> = accuracy_table(user_info)
+        .into_iter()
+        .map(|row| {
+            context! {
+                label => row.label,
+                reading => row.reading.to_string(),
+                meaning => row.meaning.to_string(),
+                total => row.total.to_string(),
+            }
+        })
+        .collect();
+    let days = |days: f64| format!("{:.1}", days);
+    let level_times: Vec<minijinja::Value> = level_times(user_info, current_date)
+        .into_iter()
+        .map(|level| {
+            context! {
+                level => level.level,
+                times => level.times,
+                total_days => days(level.total_days),
+                current_days => level.current_days.map(days).unwrap_or_else(|| "--".to_string()),
+            }
+        })
+        .collect();
+
+    context! {
+        username => user_info.get_user_name(),
+        level => user_info.get_level(),
+        started_date => started_date,
+        start_day_count => days_since_start,
+        reset_count => reset_count,
+        resets => resets,
+        started_day => started_date.format("%Y-%m-%d").to_string(),
+        reset_day_count => days_since_reset,
+        levels_passed_since_reset => progress.levels_passed_since,
+        levels_passed_all_time => progress.levels_passed_all_time,
+        items_passed_since_reset => progress.items_passed_since,
+        items_passed_now => progress.items_passed_now,
+        level_times => level_times,
+        kanji_learned => user_info.get_kanji_learned(),
+        radicals_learned => user_info.get_radicals_learned(),
+        vocab_learned => user_info.get_vocab_learned(),
+        kana_vocab_learned => user_info.get_kana_vocab_learned(),
+        all_vocab_learned => user_info.get_all_vocab_learned(),
+        accuracy => accuracy,
+    }
 }
 
 /// /info GET
-/// 
+///
 /// This has the most logic to it. Partly because of all the semantics of whether the user has a cookie, whether the cookie has a valid token, and whether the token has actually associated with
-/// a wanikani account. If all of these conditions are met, we first check if our cache has the user info. If it does, we display the user info. If it does not, we call the wanikani API to get the user info.
+/// a wanikani account. If all of these conditions are met, we first check if our cache has the user info. If it does, we display the user info. If it does not, we start fetching it from the wanikani API in the background and send a placeholder page that follows along over /info/progress.
 /// If the token is invalid, we remove the cookie and redirect to the login page. If the user has no cookie, we redirect to the login page. If the user has a cookie but no token, we remove the cookie and redirect to the login page.
 #[axum::debug_handler]
-async fn get_info(jar: CookieJar, State(state): State<AppState>) -> Response {
-    if let Some(user_uuid) = jar.get("user_uuid") {
-        let user_token_state = state.cookie_to_token.read().await;
-        let user_token = user_token_state.get(user_uuid.value());
-
-        if let Some(user_token) = user_token {
-            if let Some(user_info) = state.get_or_cache_user_data(user_token).await {
-                // If we made ALL the way here, we have a user info to display
-                let template = state.env.get_template("info").unwrap();
-
-                let started_date = user_info.get_started_at();
-                let current_date = chrono::Local::now();
-                let days_since_start = (current_date - started_date).num_days();
-                let reset_count = user_info.get_num_of_resets();
-                let reset_date = user_info
-                    .get_date_of_most_recent_reset()
-                    .unwrap_or(started_date);
-                let days_since_reset = (current_date - reset_date).num_days();
-
-                let context = context! {
-                    username => user_info.get_user_name(),
-                    level => user_info.get_level(),
-                    started_date => started_date,
-                    start_day_count => days_since_start,
-                    reset_count => reset_count,
-                    reset_date => reset_date,
-                    reset_day_count => days_since_reset,
-                    kanji_learned => user_info.get_kanji_learned(),
-                    radicals_learned => user_info.get_radicals_learned(),
-                    vocab_learned => user_info.get_vocab_learned(),
-                    total_reading_count => user_info.get_total_reading_count(),
-                    total_meaning_count => user_info.get_total_meaning_count(),
-                    total_review_count => user_info.get_total_count(),
-                    total_correct_count => user_info.get_total_correct_count(),
-                    total_correct_reading_count => user_info.get_total_correct_reading_count(),
-                    total_correct_meaning_count => user_info.get_total_correct_meaning_count(),
-                    total_incorrect_count => user_info.get_total_incorrect_count(),
-                    total_incorrect_reading_count => user_info.get_total_incorrect_reading_count(),
-                    total_incorrect_meaning_count => user_info.get_total_incorrect_meaning_count(),
-                    total_accuracy => format!("{:.2}",user_info.get_total_accuracy()),
-                    reading_accuracy => format!("{:.2}",user_info.get_total_reading_accuracy()),
-                    meaning_accuracy => format!("{:.2}",user_info.get_total_meaning_accuracy()),
-                    radical_meaning_accuracy => format!("{:.2}",user_info.get_radical_meaning_accuracy()),
-                    kanji_reading_accuracy => format!("{:.2}",user_info.get_kanji_reading_accuracy()),
-                    kanji_meaning_accuracy => format!("{:.2}",user_info.get_kanji_meaning_accuracy()),
-                    kanji_total_accuracy => format!("{:.2}",user_info.get_kanji_total_accuracy()),
-                    vocab_reading_accuracy => format!("{:.2}",user_info.get_vocab_reading_accuracy()),
-                    vocab_meaning_accuracy => format!("{:.2}",user_info.get_vocab_meaning_accuracy()),
-                    vocab_total_accuracy => format!("{:.2}",user_info.get_vocab_total_accuracy()),
-                };
+async fn get_info(
+    jar: PrivateCookieJar,
+    State(state): State<AppState>,
+    Extension(csrf): Extension<CsrfToken>,
+) -> Result<Response, AppError> {
+    if let Some(user_uuid) = jar.get(&state.config.session.cookie_name) {
+        let user_token = state.sessions.get(user_uuid.value()).await;
+
+        if let Some(user_token) = &user_token {
+            let last_failure = state.load_failures.get(user_token).await;
+            let token_rejected = last_failure == Some(WaniKaniError::InvalidToken);
+            let cached = state.user_info_cache.contains_key(user_token);
 
-                let rendered = template.render(context).unwrap();
+            if let (Some(err), false, false) = (last_failure, token_rejected, cached) {
+                // the last load failed for some other reason, like a token that can't read everything or WaniKani being down. That is
+                // shown with its own message until it drops out of `load_failures`, rather than starting the same fetch over on every visit
+                return Err(err.into());
+            }
+
+            if !token_rejected && !cached {
+                // nothing cached yet, so send the page straight away and let it follow along with the fetch over /info/progress
+                state.start_loading(user_token).await;
+
+                let template = state.env.get_template("info")?;
+                let (jar, flash) = take_flash(&state.config.session, jar);
+                let rendered = template
+                    .render(context! { flash => flash, csrf_token => csrf.0, loading => true })?;
+
+                return Ok((jar, Html(rendered)).into_response());
+            }
+
+            if !token_rejected {
+                match state.get_or_cache_user_data(user_token).await {
+                    Ok(user_info) => {
+                        // If we made ALL the way here, we have a user info to display
+                        let template = state.env.get_template("info")?;
+
+                        let (jar, flash) = take_flash(&state.config.session, jar);
+                        let context = context! { flash => flash, csrf_token => csrf.0, ..info_context(&user_info) };
 
-                return Html(rendered).into_response();
+                        let rendered = template.render(context)?;
+
+                        return Ok((jar, Html(rendered)).into_response());
+                    }
+                    Err(WaniKaniError::InvalidToken) => {}
+                    Err(err) => return Err(err.into()),
+                }
             }
 
             // if we get to this point, the user has a cookie with an uuid and that uuid has an associated token
-            // but the token was unable to be used to get user info which means wanikani has no account associated with that token
+            // but wanikani rejected the token, which means wanikani has no account associated with that token
             // so we remove the cookie and redirect to login
-            let jar = jar.remove(Cookie::from("user_uuid"));
+            let jar = jar.remove(removal_cookie(&state.config.session));
 
-            let result: Result<(CookieJar, Redirect), StatusCode> = Ok((
-                jar.add(Cookie::new(
-                    "flash",
+            let result: Result<(PrivateCookieJar, Redirect), StatusCode> = Ok((
+                jar.add(flash_cookie(
+                    &state.config.session,
                     "No account associated with that Token",
                 )),
                 Redirect::to("/login"),
             ));
 
-            return result.into_response();
+            return Ok(result.into_response());
         }
 
         // if we get to this point, the user has a cookie with an uuid but that uuid has no associated token
-        // so we remove the cookie and redirect to login
-        let result: Result<(CookieJar, Redirect), StatusCode> = Ok((
-            jar.remove(Cookie::from("user_uuid")),
+        // so we remove the cookie and redirect to login with a note explaining why
+        let jar = jar.remove(removal_cookie(&state.config.session));
+
+        let result: Result<(PrivateCookieJar, Redirect), StatusCode> = Ok((
+            jar.add(flash_cookie(
+                &state.config.session,
+                "Your session has expired, please log in again",
+            )),
             Redirect::to("/login"),
         ));
 
-        return result.into_response();
+        return Ok(result.into_response());
     }
 
     // if we get to this point, the user has no cookie so has never logged in and should be redirected to login
-    let result: Result<(CookieJar, Redirect), StatusCode> = Ok((jar, Redirect::to("/login")));
+    let result: Result<(PrivateCookieJar, Redirect), StatusCode> =
+        Ok((jar, Redirect::to("/login")));
 
-    result.into_response()
+    Ok(result.into_response())
+}
+
+/// /info/progress GET
+///
+/// The loading version of the info page connects here with the htmx SSE extension while the user's data is being fetched. Every page that
+/// comes in from WaniKani is sent as a `progress` event, and once the fetch is over a single `complete` event carries either the stats partial
+/// or a note saying what to do next, after which the stream ends. Connecting when the data is already cached just sends `complete` straight away.
+async fn get_info_progress(
+    jar: PrivateCookieJar,
+    State(state): State<AppState>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, StatusCode> {
+    let user_token = state
+        .token_for_session(&jar)
+        .await
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+
+    let shutdown_state = state.clone();
+    // a recent failure is reported straight away instead of being fetched again
+    let progress = if state.user_info_cache.contains_key(&user_token)
+        || state.load_failures.contains_key(&user_token)
+    {
+        None
+    } else {
+        Some(state.start_loading(&user_token).await)
+    };
+
+    let stream = stream::unfold(Some(progress), move |step| {
+        let state = state.clone();
+        let user_token = user_token.clone();
+
+        async move {
+            match step? {
+                Some(mut progress) => {
+                    let current = progress.borrow_and_update().to_string();
+
+                    let next = match progress.changed().await {
+                        Ok(()) => Some(Some(progress)),
+                        Err(_) => Some(None),
+                    };
+
+                    Some((Ok(Event::default().event("progress").data(current)), next))
+                }
+                None => {
+                    let rendered = match state.user_info_cache.get(&user_token).await {
+                        Some(user_info) => state
+                            .env
+                            .get_template("info_stats")
+                            .and_then(|template| template.render(info_context(&user_info)))
+                            .map_err(AppError::from),
+                        None => Err(AppError::from(
+                            state
+                                .load_failures
+                                .get(&user_token)
+                                .await
+                                .unwrap_or(WaniKaniError::Unavailable),
+                        )),
+                    };
+                    let rendered = rendered.unwrap_or_else(|err| {
+                        // /info knows what to do with each failure, including logging the user out for a rejected token
+                        format!("<p>{} <a href=\"/info\">Continue</a></p>", err.message())
+                    });
+
+                    Some((Ok(Event::default().event("complete").data(rendered)), None))
+                }
+            }
+        }
+    });
+
+    // an open stream would otherwise hold up a graceful shutdown until the drain period runs out
+    let stream = stream.take_until(async move { shutdown_state.shutting_down().await });
+
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+/// /refresh POST
+///
+/// The refresh button on the info page posts here with htmx. The user's entry is thrown out of the cache and rebuilt from WaniKani, and only the stats
+/// part of the page is sent back to be swapped in. Each user gets one refresh per `refresh_cooldown_secs`; pressing it again sooner just re-sends the cached
+/// stats with a note saying how long to wait. htmx won't swap in error responses, which is why the cooldown answer is still a 200. For the same reason
+/// a refresh that fails puts the old data back and shows the error as a note on top of it, since the user still has perfectly good stats to look at.
+async fn post_refresh(
+    jar: PrivateCookieJar,
+    State(state): State<AppState>,
+) -> Result<Response, AppError> {
+    let Some(user_token) = state.token_for_session(&jar).await else {
+        return Ok((StatusCode::UNAUTHORIZED, [("HX-Redirect", "/login")]).into_response());
+    };
+
+    let previous = state.user_info_cache.get(&user_token).await;
+
+    // Claiming the cooldown and checking for it happen in one step, so two refreshes sent at the same time can't both
+    // see an empty slot and both go to WaniKani.
+    let cooldown = state
+        .refresh_cooldowns
+        .entry(user_token.clone())
+        .or_insert_with(async { Instant::now() })
+        .await;
+
+    let notice = if cooldown.is_fresh() {
+        state.user_info_cache.invalidate(&user_token).await;
+
+        "Your data has been refreshed".to_string()
+    } else {
+        let wait = state
+            .config
+            .cache
+            .refresh_cooldown()
+            .saturating_sub(cooldown.value().elapsed());
+
+        format!(
+            "Your data was refreshed recently, you can refresh again in {} seconds",
+            wait.as_secs()
+        )
+    };
+
+    let (user_info, notice) = match (state.get_or_cache_user_data(&user_token).await, previous) {
+        (Ok(user_info), _) => (user_info, notice),
+        (Err(err), Some(previous)) => {
+            tracing::warn!(error = %err, "refresh failed, keeping the previous data");
+
+            state
+                .user_info_cache
+                .insert(user_token.clone(), previous.clone())
+                .await;
+
+            (previous, AppError::from(err).message())
+        }
+        (Err(WaniKaniError::InvalidToken), None) => {
+            return Ok((StatusCode::UNAUTHORIZED, [("HX-Redirect", "/info")]).into_response());
+        }
+        (Err(err), None) => return Err(err.into()),
+    };
+
+    let template = state.env.get_template("info_stats")?;
+    let rendered = template.render(context! { notice => notice, ..info_context(&user_info) })?;
+
+    Ok(Html(rendered).into_response())
+}
+
+/// The user behind the session cookie and their data, for the routes that hand data over as a file instead of a page. Anyone
+/// without a session is sent to log in first, the same as /info does, so following a download link while logged out ends up somewhere useful.
+/// Whatever should be sent back instead of the file is the error.
+async fn export_user_info(
+    jar: &PrivateCookieJar,
+    state: &AppState,
+) -> Result<CompleteUserInfo, Response> {
+    let Some(user_token) = state.token_for_session(jar).await else {
+        return Err(Redirect::to("/login").into_response());
+    };
+
+    match state.get_or_cache_user_data(&user_token).await {
+        Ok(user_info) => Ok(user_info),
+        // /info knows how to tell the user their token stopped working
+        Err(WaniKaniError::InvalidToken) => Err(Redirect::to("/info").into_response()),
+        Err(err) => Err(AppError::from(err).into_response()),
+    }
+}
+
+/// /compare GET
+///
+/// What changed between two days: subjects passed, burned and turned into leeches, how accuracy moved and how the SRS groups
+/// shifted. Both dates are optional and default to the last week, which is what a weekly look back wants. A date without a snapshot
+/// of its own uses the latest one before it.
+async fn get_compare(
+    jar: PrivateCookieJar,
+    Query(compare_query): Query<CompareQuery>,
+    State(state): State<AppState>,
+) -> Result<Response, AppError> {
+    let Some(user_token) = state.token_for_session(&jar).await else {
+        return Ok(Redirect::to("/login").into_response());
+    };
+
+    // fetching the data is what takes a snapshot, so this makes sure there is at least today's
+    // the latest data is also what names the subjects in the lists
+    let user_info = match state.get_or_cache_user_data(&user_token).await {
+        Ok(user_info) => user_info,
+        Err(WaniKaniError::InvalidToken) => return Ok(Redirect::to("/info").into_response()),
+        Err(err) => return Err(err.into()),
+    };
+
+    let to = compare_query
+        .to
+        .unwrap_or_else(|| chrono::Local::now().date_naive());
+    let from = compare_query
+        .from
+        .unwrap_or(to - chrono::Duration::days(7))
+        .min(to);
+
+    let (Some(older), Some(newer)) = (
+        state.history.on_or_before(&user_token, from).await,
+        state.history.on_or_before(&user_token, to).await,
+    ) else {
+        return Ok(Redirect::to("/info").into_response());
+    };
+
+    let diff = compare(&older, &newer, &user_info);
+    let percent = |accuracy: Option<f64>| {
+        accuracy
+            .map(|accuracy| format!("{:.2}%", accuracy))
+            .unwrap_or_else(|| "--".to_string())
+    };
+    let accuracy: Vec<minijinja::Value> = diff
+        .accuracy
+        .iter()
+        .map(|row| {
+            context! {
+                label => row.label,
+                from => percent(row.from),
+                to => percent(row.to),
+                change => row.change.map(|change| format!("{:+.2}", change)).unwrap_or_else(|| "--".to_string()),
+            }
+        })
+        .collect();
+
+    let template = state.env.get_template("compare")?;
+    let rendered = template.render(context! {
+        from => from,
+        to => to,
+        dates => state.history.dates(&user_token).await,
+        same_snapshot => Arc::ptr_eq(&older, &newer),
+        from_taken => diff.from.format("%Y-%m-%d %H:%M").to_string(),
+        to_taken => diff.to.format("%Y-%m-%d %H:%M").to_string(),
+        accuracy => accuracy,
+        ..minijinja::Value::from_serialize(&diff)
+    })?;
+
+    Ok(Html(rendered).into_response())
+}
+
+/// /export/items.csv GET
+///
+/// Every subject the user has been assigned, one row each, with its SRS stage and review counts. This is meant for loading into
+/// a spreadsheet or a notebook, so the columns are exactly the fields of `ItemRow` and timestamps are RFC 3339.
+async fn get_export_items_csv(
+    jar: PrivateCookieJar,
+    State(state): State<AppState>,
+) -> Result<Response, AppError> {
+    let user_info = match export_user_info(&jar, &state).await {
+        Ok(user_info) => user_info,
+        Err(response) => return Ok(response),
+    };
+
+    let mut writer = csv::Writer::from_writer(Vec::new());
+
+    for row in item_rows(&user_info) {
+        writer.serialize(row)?;
+    }
+
+    let body = writer
+        .into_inner()
+        .map_err(|err| csv::Error::from(err.into_error()))?;
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"items.csv\"",
+            ),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+/// /export/items.json GET
+///
+/// The same rows as /export/items.csv as a JSON array, with reviews that haven't happened yet as `null`.
+async fn get_export_items_json(
+    jar: PrivateCookieJar,
+    State(state): State<AppState>,
+) -> Result<Response, AppError> {
+    let user_info = match export_user_info(&jar, &state).await {
+        Ok(user_info) => user_info,
+        Err(response) => return Ok(response),
+    };
+
+    Ok((
+        [(
+            header::CONTENT_DISPOSITION,
+            "attachment; filename=\"items.json\"",
+        )],
+        Json(item_rows(&user_info)),
+    )
+        .into_response())
+}
+
+/// /api/accuracy GET
+///
+/// Accuracy for whatever slice of the user's reviews the query string asks for, so scripts don't have to wait for a getter to be added
+/// for the combination they care about. `types` takes WaniKani's names separated by commas, `min_level` and `max_level` are inclusive and
+/// `question` is `meaning` or `reading`. Anything left out counts everything. `percentage` is `null` when nothing matched.
+async fn get_api_accuracy(
+    jar: PrivateCookieJar,
+    Query(accuracy_query): Query<AccuracyQuery>,
+    State(state): State<AppState>,
+) -> Result<Response, AppError> {
+    let user_info = match export_user_info(&jar, &state).await {
+        Ok(user_info) => user_info,
+        Err(response) => return Ok(response),
+    };
+
+    let mut stats = user_info.stats().levels((
+        accuracy_query
+            .min_level
+            .map_or(Bound::Unbounded, Bound::Included),
+        accuracy_query
+            .max_level
+            .map_or(Bound::Unbounded, Bound::Included),
+    ));
+
+    if let Some(types) = &accuracy_query.types {
+        let Some(types) = types
+            .split(',')
+            .map(|name| SubjectType::from_name(name.trim()))
+            .collect::<Option<Vec<SubjectType>>>()
+        else {
+            return Ok((StatusCode::BAD_REQUEST, "Unknown subject type").into_response());
+        };
+
+        stats = stats.types(types);
+    }
+
+    if let Some(question) = accuracy_query.question {
+        stats = stats.question(question);
+    }
+
+    let accuracy = stats.accuracy();
+
+    Ok(Json(serde_json::json!({
+        "correct": accuracy.correct,
+        "incorrect": accuracy.incorrect,
+        "total": accuracy.total(),
+        "percentage": accuracy.percentage(),
+    }))
+    .into_response())
+}
+
+/// /export/anki.tsv GET
+///
+/// A deck of the user's items for drilling outside of WaniKani's SRS, as a text file Anki imports directly. The query string picks what goes in:
+/// `leeches=true` for only the items `report::leeches` finds, `min_level` and `max_level` for a range of levels, and `types` for a comma separated
+/// list of subject types. Leaving all of them off exports every assigned item.
+async fn get_export_anki(
+    jar: PrivateCookieJar,
+    State(state): State<AppState>,
+    Query(filter): Query<DeckFilter>,
+) -> Result<Response, AppError> {
+    let user_info = match export_user_info(&jar, &state).await {
+        Ok(user_info) => user_info,
+        Err(response) => return Ok(response),
+    };
+
+    let mut body = Vec::new();
+    write_tsv(&anki_notes(&user_info, &filter), &mut body)?;
+
+    Ok((
+        [
+            (
+                header::CONTENT_TYPE,
+                "text/tab-separated-values; charset=utf-8",
+            ),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"wanikani.tsv\"",
+            ),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+/// /settings GET
+///
+/// Where the user turns on the things that work without them being logged in, like the calendar feed. Only someone with a session can get here,
+/// and everyone else is sent to log in.
+async fn get_settings(
+    jar: PrivateCookieJar,
+    headers: HeaderMap,
+    State(state): State<AppState>,
+    Extension(csrf): Extension<CsrfToken>,
+) -> Result<Response, AppError> {
+    let Some(user_token) = state.token_for_session(&jar).await else {
+        return Ok(Redirect::to("/login").into_response());
+    };
+
+    let calendar = state.calendars.for_token(&user_token).await;
+    let calendar_url = calendar
+        .as_ref()
+        .map(|(secret, _)| format!("{}/calendar/{}.ics", state.public_url(&headers), secret));
+    let threshold = calendar
+        .map(|(_, threshold)| threshold)
+        .unwrap_or(calendar::DEFAULT_THRESHOLD);
+
+    let profile = state.profiles.for_token(&user_token).await;
+    let profile_url = profile
+        .as_ref()
+        .map(|(slug, _)| format!("{}/u/{}", state.public_url(&headers), slug));
+    let (profile_slug, profile_fields) = match profile {
+        Some(profile) => profile,
+        // suggest their WaniKani username, if we already know it, for a first profile
+        None => (
+            state
+                .user_info_cache
+                .get(&user_token)
+                .await
+                .map(|user_info| user_info.get_user_name().to_ascii_lowercase())
+                .unwrap_or_default(),
+            ProfileFields::default(),
+        ),
+    };
+
+    let (jar, flash) = take_flash(&state.config.session, jar);
+    let template = state.env.get_template("settings")?;
+    let rendered = template.render(context! {
+        flash => flash,
+        csrf_token => csrf.0,
+        calendar_url => calendar_url,
+        threshold => threshold,
+        profile_url => profile_url,
+        profile_slug => profile_slug,
+        show_level => profile_fields.level,
+        show_learned => profile_fields.learned,
+        show_accuracy => profile_fields.accuracy,
+        show_level_ups => profile_fields.level_ups,
+    })?;
+
+    Ok((jar, Html(rendered)).into_response())
+}
+
+/// /settings/calendar POST
+///
+/// Turns the calendar feed on, or changes its threshold if it is already on. The feed URL stays the same either way.
+async fn post_settings_calendar(
+    jar: PrivateCookieJar,
+    State(state): State<AppState>,
+    Form(calendar_form): Form<CalendarForm>,
+) -> Response {
+    let Some(user_token) = state.token_for_session(&jar).await else {
+        return Redirect::to("/login").into_response();
+    };
+
+    state
+        .calendars
+        .subscribe(&user_token, calendar_form.threshold.max(1))
+        .await;
+
+    (
+        jar.add(flash_cookie(
+            &state.config.session,
+            "Your calendar feed is on",
+        )),
+        Redirect::to("/settings"),
+    )
+        .into_response()
+}
+
+/// /settings/calendar/revoke POST
+///
+/// Turns the calendar feed off. Its URL stops working straight away, and turning the feed back on later hands out a new one.
+async fn post_settings_calendar_revoke(
+    jar: PrivateCookieJar,
+    State(state): State<AppState>,
+) -> Response {
+    let Some(user_token) = state.token_for_session(&jar).await else {
+        return Redirect::to("/login").into_response();
+    };
+
+    state.calendars.revoke(&user_token).await;
+
+    (
+        jar.add(flash_cookie(
+            &state.config.session,
+            "Your calendar feed has been turned off",
+        )),
+        Redirect::to("/settings"),
+    )
+        .into_response()
+}
+
+/// /settings/profile POST
+///
+/// Publishes the user's public profile with the parts they ticked, or updates the one they have. A slug that is badly formed or belongs
+/// to someone else sends them back to the settings page with a note saying so, and leaves whatever they had published alone.
+async fn post_settings_profile(
+    jar: PrivateCookieJar,
+    State(state): State<AppState>,
+    Form(profile_form): Form<ProfileForm>,
+) -> Response {
+    let Some(user_token) = state.token_for_session(&jar).await else {
+        return Redirect::to("/login").into_response();
+    };
+
+    let fields = ProfileFields {
+        level: profile_form.level,
+        learned: profile_form.learned,
+        accuracy: profile_form.accuracy,
+        level_ups: profile_form.level_ups,
+    };
+    let message = match state
+        .profiles
+        .publish(&user_token, &profile_form.slug, fields)
+        .await
+    {
+        Ok(_) => "Your profile is published",
+        Err(err) => err.message(),
+    };
+
+    (
+        jar.add(flash_cookie(&state.config.session, message)),
+        Redirect::to("/settings"),
+    )
+        .into_response()
+}
+
+/// /settings/profile/revoke POST
+///
+/// Takes the user's public profile down. Anyone who had it open, or a cache that kept a copy, can still see the copy they have until it
+/// expires, but nobody gets a fresh one.
+async fn post_settings_profile_revoke(
+    jar: PrivateCookieJar,
+    State(state): State<AppState>,
+) -> Response {
+    let Some(user_token) = state.token_for_session(&jar).await else {
+        return Redirect::to("/login").into_response();
+    };
+
+    state.profiles.revoke(&user_token).await;
+
+    (
+        jar.add(flash_cookie(
+            &state.config.session,
+            "Your profile is no longer public",
+        )),
+        Redirect::to("/settings"),
+    )
+        .into_response()
+}
+
+/// /u/{slug} GET
+///
+/// Someone's public profile. Only the parts they chose are put into the template context at all, and nothing that identifies their
+/// account beyond the slug they picked. Whoever is looking is usually not the owner, so they never wait on WaniKani: the page comes from
+/// the user info cache, or failing that the page saved the last time it was rendered, and at most one load per `user_info_ttl_secs` is
+/// started in the background to bring it up to date. The page is the same for everyone who asks for it, so it is marked as cacheable
+/// until the data behind it expires, with an ETag that changes whenever the data is refetched or the choice of parts changes.
+async fn get_profile(
+    Path(slug): Path<String>,
+    headers: HeaderMap,
+    State(state): State<AppState>,
+) -> Result<Response, AppError> {
+    let slug = slug.to_ascii_lowercase();
+    let Some(profile) = state.profiles.get(&slug).await else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+
+    if state.load_failures.get(&profile.token).await == Some(WaniKaniError::InvalidToken) {
+        state.profiles.revoke(&profile.token).await;
+
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    }
+
+    let page = match state.user_info_cache.get(&profile.token).await {
+        Some(user_info) => match state.profiles.saved_page(&slug, &profile).await {
+            Some(saved) if saved.fetched_at == *user_info.get_fetched_at() => Some(saved),
+            _ => {
+                let template = state.env.get_template("profile")?;
+                let page = SavedPage {
+                    token: profile.token.clone(),
+                    fields: profile.fields,
+                    fetched_at: *user_info.get_fetched_at(),
+                    html: template
+                        .render(profile_context(&slug, profile.fields, &user_info))?
+                        .into(),
+                };
+
+                state.profiles.save_page(&slug, page.clone()).await;
+
+                Some(page)
+            }
+        },
+        None => {
+            // claiming the slot and checking for it happen in one step, so a burst of visitors starts one load between them
+            let load = state
+                .profile_loads
+                .entry(profile.token.clone())
+                .or_insert_with(async {})
+                .await;
+
+            if load.is_fresh() {
+                state.start_loading(&profile.token).await;
+            }
+
+            state.profiles.saved_page(&slug, &profile).await
+        }
+    };
+
+    let Some(page) = page else {
+        let template = state.env.get_template("profile")?;
+        let rendered = template.render(context! { slug => slug, updating => true })?;
+
+        return Ok((
+            StatusCode::SERVICE_UNAVAILABLE,
+            [
+                (header::RETRY_AFTER, "60"),
+                (header::CACHE_CONTROL, "no-store"),
+            ],
+            Html(rendered),
+        )
+            .into_response());
+    };
+
+    let etag = format!(
+        "\"{}-{}\"",
+        page.fetched_at.timestamp(),
+        profile.fields.bits()
+    );
+    let expires_at = page.fetched_at
+        + chrono::Duration::from_std(state.config.cache.user_info_ttl())
+            .unwrap_or(chrono::Duration::zero());
+    let max_age = (expires_at - chrono::Local::now()).num_seconds().max(0);
+    let cache_control = format!("public, max-age={}", max_age);
+    let cache_headers = [
+        (header::ETAG, etag.clone()),
+        (header::CACHE_CONTROL, cache_control),
+    ];
+
+    if headers
+        .get(header::IF_NONE_MATCH)
+        .is_some_and(|if_none_match| if_none_match.as_bytes() == etag.as_bytes())
+    {
+        return Ok((StatusCode::NOT_MODIFIED, cache_headers).into_response());
+    }
+
+    Ok((cache_headers, Html(page.html.to_string())).into_response())
+}
+
+/// The context for a public profile, holding only the parts `fields` lets through.
+fn profile_context(
+    slug: &str,
+    fields: ProfileFields,
+    user_info: &CompleteUserInfo,
+) -> minijinja::Value {
+    let level = fields.level.then(|| user_info.get_level());
+    let learned = fields.learned.then(|| {
+        context! {
+            radicals => user_info.get_radicals_learned(),
+            kanji => user_info.get_kanji_learned(),
+            vocabulary => user_info.get_vocab_learned(),
+            kana_vocabulary => user_info.get_kana_vocab_learned(),
+            all_vocabulary => user_info.get_all_vocab_learned(),
+        }
+    });
+    let accuracy = fields.accuracy.then(|| {
+        accuracy_table(user_info)
+            .into_iter()
+            .map(|row| {
+                context! {
+                    label => row.label,
+                    reading => row.reading.to_string(),
+                    meaning => row.meaning.to_string(),
+                    total => row.total.to_string(),
+                }
+            })
+            .collect::<Vec<_>>()
+    });
+    let level_ups = fields.level_ups.then(|| {
+        let history = level_ups(user_info, chrono::Local::now());
+        let longest = history
+            .iter()
+            .map(|level_up| level_up.days)
+            .fold(1.0, f64::max);
+
+        history
+            .into_iter()
+            .map(|level_up| {
+                context! {
+                    level => level_up.level,
+                    days => format!("{:.1}", level_up.days),
+                    width => format!("{:.1}", level_up.days / longest * 100.0),
+                    current => level_up.passed_at.is_none(),
+                }
+            })
+            .collect::<Vec<_>>()
+    });
+
+    context! {
+        slug => slug,
+        level => level,
+        learned => learned,
+        accuracy => accuracy,
+        level_ups => level_ups,
+    }
+}
+
+/// /groups GET
+///
+/// The groups the user is in, and a form to start a new one.
+async fn get_groups(
+    jar: PrivateCookieJar,
+    State(state): State<AppState>,
+    Extension(csrf): Extension<CsrfToken>,
+) -> Result<Response, AppError> {
+    let Some(user_token) = state.token_for_session(&jar).await else {
+        return Ok(Redirect::to("/login").into_response());
+    };
+
+    let groups: Vec<minijinja::Value> = state
+        .groups
+        .for_member(&user_token)
+        .await
+        .into_iter()
+        .map(|(id, group)| {
+            context! {
+                id => id,
+                name => group.name,
+                members => group.members.len(),
+            }
+        })
+        .collect();
+
+    let (jar, flash) = take_flash(&state.config.session, jar);
+    let template = state.env.get_template("groups")?;
+    let rendered =
+        template.render(context! { flash => flash, csrf_token => csrf.0, groups => groups })?;
+
+    Ok((jar, Html(rendered)).into_response())
+}
+
+/// /groups POST
+///
+/// Starts a group with the user as its owner and only member, and takes them to it so they can copy the invite link.
+async fn post_groups(
+    jar: PrivateCookieJar,
+    State(state): State<AppState>,
+    Form(group_form): Form<GroupForm>,
+) -> Response {
+    let Some(user_token) = state.token_for_session(&jar).await else {
+        return Redirect::to("/login").into_response();
+    };
+
+    let name = group_form.name.trim();
+
+    if name.is_empty() || name.chars().count() > 64 {
+        return (
+            jar.add(flash_cookie(
+                &state.config.session,
+                "Group names are 1 to 64 characters",
+            )),
+            Redirect::to("/groups"),
+        )
+            .into_response();
+    }
+
+    match state.groups.create(name, &user_token).await {
+        Ok(id) => Redirect::to(&format!("/groups/{}", id)).into_response(),
+        Err(err) => (
+            jar.add(flash_cookie(&state.config.session, err.message())),
+            Redirect::to("/groups"),
+        )
+            .into_response(),
+    }
+}
+
+/// The latest stats the group has for a member. Members whose data is in the cache get their stats worked out from it again. Nobody's
+/// data is fetched just because someone else looked at the leaderboard, so for everyone else it is whatever was saved the last time
+/// they were around, which `get_group` marks as stale once it is older than the cache's TTL.
+async fn group_member_stats(state: &AppState, token: &UserToken) -> Option<MemberStats> {
+    if let Some(user_info) = state.user_info_cache.get(token).await {
+        let stats = MemberStats::new(&user_info);
+        state.groups.record_stats(token, stats.clone()).await;
+
+        return Some(stats);
+    }
+
+    state.groups.member_stats(token).await
+}
+
+/// /groups/{id} GET
+///
+/// The group's leaderboard, highest level first and then by how much each member passed in the last week. Only members can see it;
+/// for anyone else the group doesn't exist. Stats older than the user info cache's TTL are marked with when they are from.
+async fn get_group(
+    jar: PrivateCookieJar,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    Extension(csrf): Extension<CsrfToken>,
+) -> Result<Response, AppError> {
+    let Some(user_token) = state.token_for_session(&jar).await else {
+        return Ok(Redirect::to("/login").into_response());
+    };
+    let Some(group) = state
+        .groups
+        .get(&id)
+        .await
+        .filter(|group| group.members.contains(&user_token))
+    else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+
+    let stale_after = chrono::Duration::from_std(state.config.cache.user_info_ttl())
+        .unwrap_or(chrono::Duration::MAX);
+    let now = chrono::Local::now();
+    let mut members = Vec::new();
+
+    for member in &group.members {
+        members.push((
+            member == &user_token,
+            group_member_stats(&state, member).await,
+        ));
+    }
+
+    // members we know nothing about yet go to the bottom
+    members.sort_by_key(|(_, stats)| {
+        std::cmp::Reverse(
+            stats
+                .as_ref()
+                .map(|stats| (stats.level, stats.passed_this_week)),
+        )
+    });
+
+    let rows: Vec<minijinja::Value> = members
+        .into_iter()
+        .map(|(you, stats)| match stats {
+            Some(stats) => context! {
+                you => you,
+                username => stats.username,
+                level => stats.level,
+                passed_this_week => stats.passed_this_week,
+                accuracy => stats.accuracy.map(|accuracy| format!("{:.2}%", accuracy)).unwrap_or_else(|| "--".to_string()),
+                days_on_level => stats.days_on_level.map(|days| format!("{:.1}", days)).unwrap_or_else(|| "--".to_string()),
+                stale => now - stats.as_of > stale_after,
+                as_of => stats.as_of.format("%Y-%m-%d %H:%M").to_string(),
+            },
+            None => context! { you => you },
+        })
+        .collect();
+
+    let (jar, flash) = take_flash(&state.config.session, jar);
+    let template = state.env.get_template("group")?;
+    let rendered = template.render(context! {
+        flash => flash,
+        csrf_token => csrf.0,
+        id => id,
+        name => group.name,
+        members => rows,
+        is_owner => group.owner == user_token,
+        invite_url => format!("{}/groups/{}/join/{}", state.public_url(&headers), id, group.invite),
+    })?;
+
+    Ok((jar, Html(rendered)).into_response())
+}
+
+/// /groups/{id}/join/{invite} GET
+///
+/// Where an invite link leads. Joining is a POST like everything else that changes something, so this only asks the user whether they
+/// want to. Members are sent straight on to the group, and for anyone else an invite that isn't the group's current one is a 404.
+async fn get_group_join(
+    jar: PrivateCookieJar,
+    Path((id, invite)): Path<(String, String)>,
+    State(state): State<AppState>,
+    Extension(csrf): Extension<CsrfToken>,
+) -> Result<Response, AppError> {
+    let Some(user_token) = state.token_for_session(&jar).await else {
+        return Ok((
+            jar.add(flash_cookie(
+                &state.config.session,
+                "Log in first, then open the invite link again",
+            )),
+            Redirect::to("/login"),
+        )
+            .into_response());
+    };
+    let Some(group) = state.groups.get(&id).await else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+
+    if group.members.contains(&user_token) {
+        return Ok(Redirect::to(&format!("/groups/{}", id)).into_response());
+    }
+
+    // to anyone else, a link with an old invite says nothing about the group, not even that it exists
+    if group.invite != invite {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    }
+
+    let template = state.env.get_template("group_join")?;
+    let rendered = template.render(context! {
+        csrf_token => csrf.0,
+        id => id,
+        invite => invite,
+        name => group.name,
+        members => group.members.len(),
+    })?;
+
+    Ok(Html(rendered).into_response())
+}
+
+/// /groups/{id}/join/{invite} POST
+///
+/// Adds the user to the group, as long as the invite is still the group's current one.
+async fn post_group_join(
+    jar: PrivateCookieJar,
+    Path((id, invite)): Path<(String, String)>,
+    State(state): State<AppState>,
+) -> Response {
+    let Some(user_token) = state.token_for_session(&jar).await else {
+        return Redirect::to("/login").into_response();
+    };
+
+    match state.groups.join(&id, &invite, &user_token).await {
+        Ok(()) => Redirect::to(&format!("/groups/{}", id)).into_response(),
+        Err(err) => (
+            jar.add(flash_cookie(&state.config.session, err.message())),
+            Redirect::to("/groups"),
+        )
+            .into_response(),
+    }
+}
+
+/// /groups/{id}/leave POST
+async fn post_group_leave(
+    jar: PrivateCookieJar,
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+) -> Response {
+    let Some(user_token) = state.token_for_session(&jar).await else {
+        return Redirect::to("/login").into_response();
+    };
+
+    state.groups.leave(&id, &user_token).await;
+
+    (
+        jar.add(flash_cookie(
+            &state.config.session,
+            "You have left the group",
+        )),
+        Redirect::to("/groups"),
+    )
+        .into_response()
+}
+
+/// /groups/{id}/invite POST
+///
+/// Replaces the group's invite link, for when the old one got passed around further than it should have. Only the owner can.
+async fn post_group_invite(
+    jar: PrivateCookieJar,
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+) -> Response {
+    let Some(user_token) = state.token_for_session(&jar).await else {
+        return Redirect::to("/login").into_response();
+    };
+
+    let message = match state.groups.reset_invite(&id, &user_token).await {
+        Ok(()) => "The old invite link no longer works",
+        Err(err) => err.message(),
+    };
+
+    (
+        jar.add(flash_cookie(&state.config.session, message)),
+        Redirect::to(&format!("/groups/{}", id)),
+    )
+        .into_response()
+}
+
+/// /calendar/{secret}.ics GET
+///
+/// The review forecast as an iCalendar feed, for subscribing to from a calendar app. Calendar apps can't log in, so the secret in the URL is
+/// all that is checked. A secret that was never handed out or has been revoked gets a 404, and so does one whose token WaniKani has since
+/// stopped accepting, which also turns that feed off.
+async fn get_calendar(
+    Path(file_name): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Response, AppError> {
+    let Some(secret) = file_name.strip_suffix(".ics") else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    let Some(feed) = state.calendars.feed(secret).await else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+
+    let user_info = match state.get_or_cache_user_data(&feed.token).await {
+        Ok(user_info) => user_info,
+        Err(WaniKaniError::InvalidToken) => {
+            state.calendars.revoke(&feed.token).await;
+
+            return Ok(StatusCode::NOT_FOUND.into_response());
+        }
+        Err(err) => return Err(err.into()),
+    };
+
+    Ok((
+        [(header::CONTENT_TYPE, "text/calendar; charset=utf-8")],
+        calendar::render_feed(&user_info, feed.threshold, chrono::Local::now()),
+    )
+        .into_response())
+}
+
+/// /metrics GET
+///
+/// Everything the server and `ApiClient` have been counting, in the Prometheus text format. The session count isn't something
+/// that gets counted as it happens, so it is read off the session store whenever this is scraped.
+async fn get_metrics(State(state): State<AppState>) -> String {
+    metrics::gauge!("active_sessions").set(state.sessions.len().await as f64);
+
+    state.metrics.render()
+}
+
+/// /healthz GET
+///
+/// Answers as long as the process is up and serving requests. Nothing else is checked, so a restart is only triggered when one would help.
+async fn get_healthz() -> Json<serde_json::Value> {
+    Json(serde_json::json!({ "alive": true }))
+}
+
+/// /readyz GET
+///
+/// Whether this instance should be sent traffic, with a JSON body saying how each part of it is doing. The session store lives in
+/// memory so it is always reachable, but asking it for its size still makes sure it is answering. Any component that isn't ready turns
+/// the whole response into a 503.
+async fn get_readyz(State(state): State<AppState>) -> (StatusCode, Json<Readiness>) {
+    let missing_templates: Vec<&str> = TEMPLATE_NAMES
+        .into_iter()
+        .filter(|name| state.env.get_template(name).is_err())
+        .collect();
+    let templates = if missing_templates.is_empty() {
+        ComponentStatus::ready(None)
+    } else {
+        ComponentStatus::unavailable(format!("missing {}", missing_templates.join(", ")))
+    };
+    let sessions = ComponentStatus::ready(format!("{} live sessions", state.sessions.len().await));
+
+    let readiness = Readiness::new(BTreeMap::from([
+        ("templates", templates),
+        ("sessions", sessions),
+        ("wanikani", state.wanikani_status().await),
+    ]));
+    let status = if readiness.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (status, Json(readiness))
+}
+
+/// Runs until the server shuts down and keeps the cached data of recently active users warm, so that coming back to the /info page
+/// after the cache TTL doesn't mean waiting through a full fetch. Users are refreshed one after another since they all share the same
+/// rate limiter anyway. Only users whose data is about to expire are refreshed, oldest first, and no more than `background_refresh_batch`
+/// of them a pass so that people loading pages aren't left queueing behind the refresh. Anyone who doesn't make the cut is first in line
+/// next pass, and the margin being longer than the interval gives them that chance. A shutdown lets the refresh that is running finish, but
+/// no new ones are started.
+async fn refresh_active_users(state: AppState) {
+    let mut interval = tokio::time::interval(state.config.cache.background_refresh_interval());
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = state.shutting_down() => return,
+        }
+
+        let active_users: Vec<UserToken> = state
+            .recently_active
+            .iter()
+            .map(|(token, _)| (*token).clone())
+            .collect();
+        let mut expiring = Vec::new();
+
+        for token in active_users {
+            if let Some(user_info) = state.user_info_cache.get(&token).await {
+                if state.expires_soon(&user_info) {
+                    expiring.push((*user_info.get_fetched_at(), token));
+                }
+            }
+        }
+
+        expiring.sort_by_key(|(fetched_at, _)| *fetched_at);
+
+        for (_, token) in expiring
+            .into_iter()
+            .take(state.config.cache.background_refresh_batch)
+        {
+            if *state.shutdown.borrow() {
+                return;
+            }
+
+            state.refresh_if_expiring(&token).await;
+        }
+    }
+}
+
+/// Resolves on Ctrl+C, or on SIGTERM which is what most deploys send first.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to listen for Ctrl+C");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| EnvFilter::new("wanikani_stats=info,tower_http=info")),
+        )
+        .init();
+
+    let config = Config::load()?;
+    let metrics = telemetry::install_recorder()?;
+
     let mut env = Environment::new();
 
     env.add_template("base", include_str!("../templates/base.jinja"))
@@ -211,31 +1786,172 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     env.add_template("info", include_str!("../templates/info.jinja"))
         .unwrap();
 
-    let cache = Cache::builder()
-        .max_capacity(1000)
-        .time_to_live(Duration::from_secs(300))
-        .build();
+    env.add_template("info_stats", include_str!("../templates/info_stats.jinja"))
+        .unwrap();
+
+    env.add_template("settings", include_str!("../templates/settings.jinja"))
+        .unwrap();
+
+    env.add_template("profile", include_str!("../templates/profile.jinja"))
+        .unwrap();
+
+    env.add_template("groups", include_str!("../templates/groups.jinja"))
+        .unwrap();
+
+    env.add_template("group", include_str!("../templates/group.jinja"))
+        .unwrap();
+
+    env.add_template("group_join", include_str!("../templates/group_join.jinja"))
+        .unwrap();
+
+    env.add_template("compare", include_str!("../templates/compare.jinja"))
+        .unwrap();
+
+    let cache_config = &config.cache;
+    let requests_per_minute = NonZeroU32::new(config.rate_limit.requests_per_minute)
+        .ok_or("rate_limit.requests_per_minute must be at least 1")?;
+
+    let cookie_key = match &config.session.key {
+        Some(key) => Key::from(key.as_bytes()),
+        None => Key::generate(),
+    };
+
+    let (shutdown_sender, shutdown) = watch::channel(false);
 
     let shared_state = AppState {
-        cookie_to_token: Arc::new(RwLock::new(HashMap::new())),
-        user_info_cache: cache,
+        sessions: SessionStore::new(config.session.capacity, config.session.idle_timeout()),
+        calendars: CalendarStore::new(config.stores.calendar_capacity),
+        profiles: ProfileStore::new(config.stores.profile_capacity),
+        groups: GroupStore::new(config.stores.group_capacity),
+        history: HistoryStore::new(history::MAX_HISTORY_BYTES),
+        cookie_key,
+        user_info_cache: Cache::builder()
+            .max_capacity(cache_config.capacity)
+            .time_to_live(cache_config.user_info_ttl())
+            .build(),
+        refresh_cooldowns: Cache::builder()
+            .max_capacity(cache_config.capacity)
+            .time_to_live(cache_config.refresh_cooldown())
+            .build(),
+        profile_loads: Cache::builder()
+            .max_capacity(cache_config.capacity)
+            .time_to_live(cache_config.user_info_ttl())
+            .build(),
+        loading: Arc::new(RwLock::new(HashMap::new())),
+        load_failures: Cache::builder()
+            .max_capacity(cache_config.capacity)
+            .time_to_live(cache_config.load_failure_ttl())
+            .build(),
+        recently_active: Cache::builder()
+            .max_capacity(cache_config.capacity)
+            .time_to_idle(cache_config.active_user_idle())
+            .build(),
         rate_limiter: Arc::new(RateLimiter::direct_with_clock(
-            Quota::per_minute(nonzero!(10u32)),
+            Quota::per_minute(requests_per_minute),
             governor::clock::DefaultClock::default(),
         )),
         reqwest_client: reqwest::Client::new(),
+        api_contact: Arc::new(ApiContact::default()),
         env,
+        config: Arc::new(config.clone()),
+        metrics,
+        shutdown,
     };
 
+    let background_refresh = tokio::spawn(refresh_active_users(shared_state.clone()));
+
     let app = Router::new()
         .route("/", get(get_login))
         .route("/login", get(get_login).post(post_login))
+        .route("/logout", post(post_logout))
         .route("/info", get(get_info))
-        .with_state(shared_state);
+        .route("/info/progress", get(get_info_progress))
+        .route("/refresh", post(post_refresh))
+        .route("/export/items.csv", get(get_export_items_csv))
+        .route("/export/items.json", get(get_export_items_json))
+        .route("/export/anki.tsv", get(get_export_anki))
+        .route("/api/accuracy", get(get_api_accuracy))
+        .route("/settings", get(get_settings))
+        .route("/settings/calendar", post(post_settings_calendar))
+        .route(
+            "/settings/calendar/revoke",
+            post(post_settings_calendar_revoke),
+        )
+        .route("/settings/profile", post(post_settings_profile))
+        .route(
+            "/settings/profile/revoke",
+            post(post_settings_profile_revoke),
+        )
+        .route("/calendar/{file_name}", get(get_calendar))
+        .route("/u/{slug}", get(get_profile))
+        .route("/compare", get(get_compare))
+        .route("/groups", get(get_groups).post(post_groups))
+        .route("/groups/{id}", get(get_group))
+        .route(
+            "/groups/{id}/join/{invite}",
+            get(get_group_join).post(post_group_join),
+        )
+        .route("/groups/{id}/leave", post(post_group_leave))
+        .route("/groups/{id}/invite", post(post_group_invite))
+        .route("/metrics", get(get_metrics))
+        .route("/healthz", get(get_healthz))
+        .route("/readyz", get(get_readyz))
+        .nest_service("/static", ServeDir::new("static"))
+        .layer(from_fn_with_state(
+            (
+                shared_state.cookie_key.clone(),
+                Arc::new(config.session.clone()),
+            ),
+            verify_csrf,
+        ))
+        .layer(from_fn(telemetry::track_requests))
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(telemetry::request_span)
+                .on_response(DefaultOnResponse::new().level(Level::INFO)),
+        )
+        .with_state(shared_state.clone());
+
+    let listener = tokio::net::TcpListener::bind(config.listen_addr).await?;
+
+    tracing::info!(addr = %config.listen_addr, "listening");
+
+    let graceful_state = shared_state.clone();
+    let mut server = tokio::spawn(async move {
+        axum::serve(listener, app)
+            .with_graceful_shutdown(async move { graceful_state.shutting_down().await })
+            .await
+    });
+
+    tokio::select! {
+        result = &mut server => return Ok(result??),
+        _ = shutdown_signal() => {}
+    }
+
+    // New connections are turned away from here on, while requests that are already running, the fetches they started and the
+    // background refresh all get until the end of the drain period to finish. Whatever is still going after that is dropped.
+    tracing::info!(
+        drain_secs = config.shutdown_drain_secs,
+        "shutting down, waiting for in-flight work"
+    );
+    shutdown_sender.send_replace(true);
+
+    let drain = async {
+        let _ = server.await;
+        let _ = background_refresh.await;
+        shared_state.wait_for_loading().await;
+    };
+
+    if tokio::time::timeout(config.shutdown_drain(), drain)
+        .await
+        .is_err()
+    {
+        tracing::warn!("drain period ran out, dropping work that was still in flight");
+    }
 
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
+    shared_state.flush_stores().await;
 
-    axum::serve(listener, app).await.unwrap();
+    tracing::info!("shut down");
 
     Ok(())
 }
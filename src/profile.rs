@@ -0,0 +1,267 @@
+use crate::{UserToken, LONG_IDLE_TIMEOUT};
+use chrono::{DateTime, Local};
+use moka::future::Cache;
+use std::sync::Arc;
+
+/// Which parts of their stats a user has chosen to show. Nothing is shown unless it was ticked, so a
+/// part added later never ends up on a profile someone published before it existed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ProfileFields {
+    pub level: bool,
+    pub learned: bool,
+    pub accuracy: bool,
+    pub level_ups: bool,
+}
+
+impl ProfileFields {
+    /// Packs the fields into a number for the ETag, so changing what is shown counts as a new version.
+    pub fn bits(&self) -> u8 {
+        [self.level, self.learned, self.accuracy, self.level_ups]
+            .into_iter()
+            .enumerate()
+            .map(|(bit, shown)| (shown as u8) << bit)
+            .sum()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    pub token: UserToken,
+    pub fields: ProfileFields,
+}
+
+/// A profile page as it was last rendered, along with who it was rendered for and which fields it shows so it is
+/// never served for a profile that has since changed hands or hidden something.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SavedPage {
+    pub token: UserToken,
+    pub fields: ProfileFields,
+    pub fetched_at: DateTime<Local>,
+    pub html: Arc<str>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SlugError {
+    Invalid,
+    Taken,
+}
+
+impl SlugError {
+    /// What the settings page tells the user. These go out as flash messages, which is why they're static.
+    pub fn message(&self) -> &'static str {
+        match self {
+            SlugError::Invalid => "Profile addresses are 3 to 32 letters, digits, '-' or '_'",
+            SlugError::Taken => "Someone else already has that profile address",
+        }
+    }
+}
+
+/// Every published profile by its slug, along with which slug each token has so the settings page can show
+/// it again. A profile only points at the token; the stats themselves come out of the user info cache, and
+/// the last page rendered from them is saved so there is still something to show once that entry expires.
+/// A profile nobody has opened or changed for `LONG_IDLE_TIMEOUT` stops being published, and past `capacity`
+/// profiles the least used ones go first.
+#[derive(Clone)]
+pub struct ProfileStore {
+    profiles: Cache<String, Profile>,
+    slugs: Cache<UserToken, String>,
+    pages: Cache<String, SavedPage>,
+}
+
+impl ProfileStore {
+    pub fn new(capacity: u64) -> Self {
+        ProfileStore {
+            profiles: Cache::builder()
+                .max_capacity(capacity)
+                .time_to_idle(LONG_IDLE_TIMEOUT)
+                .build(),
+            slugs: Cache::builder()
+                .max_capacity(capacity)
+                .time_to_idle(LONG_IDLE_TIMEOUT)
+                .build(),
+            pages: Cache::builder()
+                .max_capacity(capacity)
+                .time_to_idle(LONG_IDLE_TIMEOUT)
+                .build(),
+        }
+    }
+
+    /// The profile published under `slug`. Being looked at keeps the profile alive on both sides.
+    pub async fn get(&self, slug: &str) -> Option<Profile> {
+        let profile = self.profiles.get(slug).await?;
+        self.slugs.get(&profile.token).await;
+
+        Some(profile)
+    }
+
+    /// The user's slug and what they chose to show, if they have published a profile.
+    pub async fn for_token(&self, token: &UserToken) -> Option<(String, ProfileFields)> {
+        let slug = self.slugs.get(token).await?;
+        let profile = self.profiles.get(&slug).await?;
+
+        Some((slug, profile.fields))
+    }
+
+    /// Publishes the user's profile under `slug`, or updates it. Slugs are case insensitive and stored in
+    /// lower case. Moving to a new slug frees up the old one, which stops working straight away.
+    pub async fn publish(
+        &self,
+        token: &UserToken,
+        slug: &str,
+        fields: ProfileFields,
+    ) -> Result<String, SlugError> {
+        let slug = slug.trim().to_ascii_lowercase();
+
+        if !valid_slug(&slug) {
+            return Err(SlugError::Invalid);
+        }
+
+        let profile = Profile {
+            token: token.clone(),
+            fields,
+        };
+        // claiming the slug and checking who has it happen in one step, so two users can't both get it
+        let entry = self
+            .profiles
+            .entry(slug.clone())
+            .or_insert_with(async { profile.clone() })
+            .await;
+
+        if !entry.is_fresh() {
+            if entry.value().token != *token {
+                return Err(SlugError::Taken);
+            }
+
+            self.profiles.insert(slug.clone(), profile).await;
+        }
+
+        if let Some(previous) = self.slugs.get(token).await {
+            if previous != slug {
+                self.profiles.invalidate(&previous).await;
+                self.pages.invalidate(&previous).await;
+            }
+        }
+
+        self.slugs.insert(token.clone(), slug.clone()).await;
+
+        Ok(slug)
+    }
+
+    /// Takes the profile down. Its address answers with a 404 from then on.
+    pub async fn revoke(&self, token: &UserToken) {
+        if let Some(slug) = self.slugs.remove(token).await {
+            self.profiles.invalidate(&slug).await;
+            self.pages.invalidate(&slug).await;
+        }
+    }
+
+    /// The page last saved for `profile`, as long as it still belongs to the same user and shows the same fields.
+    pub async fn saved_page(&self, slug: &str, profile: &Profile) -> Option<SavedPage> {
+        self.pages
+            .get(slug)
+            .await
+            .filter(|page| page.token == profile.token && page.fields == profile.fields)
+    }
+
+    pub async fn save_page(&self, slug: &str, page: SavedPage) {
+        self.pages.insert(slug.to_string(), page).await;
+    }
+
+    /// Settles the profiles, the slugs they are published under and their saved pages, for `AppState::flush_stores`.
+    pub async fn flush(&self) {
+        self.profiles.run_pending_tasks().await;
+        self.slugs.run_pending_tasks().await;
+        self.pages.run_pending_tasks().await;
+    }
+}
+
+fn valid_slug(slug: &str) -> bool {
+    (3..=32).contains(&slug.len())
+        && slug
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    fn token(token: &str) -> UserToken {
+        UserToken {
+            token: token.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_slugs_belong_to_one_user() {
+        let store = ProfileStore::new(10);
+        let fields = ProfileFields {
+            level: true,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            store.publish(&token("a"), "Koichi", fields).await,
+            Ok("koichi".to_string())
+        );
+        assert_eq!(
+            store.publish(&token("b"), "koichi", fields).await,
+            Err(SlugError::Taken)
+        );
+        assert_eq!(
+            store.publish(&token("b"), "no spaces", fields).await,
+            Err(SlugError::Invalid)
+        );
+        assert_eq!(store.get("koichi").await.unwrap().token, token("a"));
+    }
+
+    #[tokio::test]
+    async fn test_moving_and_revoking_free_the_slug() {
+        let store = ProfileStore::new(10);
+        let fields = ProfileFields::default();
+
+        store.publish(&token("a"), "first", fields).await.unwrap();
+        store.publish(&token("a"), "second", fields).await.unwrap();
+
+        assert_eq!(store.get("first").await, None);
+
+        store.revoke(&token("a")).await;
+
+        assert_eq!(store.get("second").await, None);
+        assert!(store.publish(&token("b"), "second", fields).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_saved_page_is_only_served_for_the_same_profile() {
+        let store = ProfileStore::new(10);
+        let fields = ProfileFields {
+            level: true,
+            ..Default::default()
+        };
+        let page = SavedPage {
+            token: token("a"),
+            fields,
+            fetched_at: Local::now(),
+            html: "<h1>koichi</h1>".into(),
+        };
+
+        store.publish(&token("a"), "koichi", fields).await.unwrap();
+        store.save_page("koichi", page.clone()).await;
+
+        let profile = store.get("koichi").await.unwrap();
+        assert_eq!(store.saved_page("koichi", &profile).await, Some(page));
+
+        let showing_less = Profile {
+            fields: ProfileFields::default(),
+            ..profile
+        };
+        assert_eq!(store.saved_page("koichi", &showing_less).await, None);
+
+        store.revoke(&token("a")).await;
+        store.publish(&token("b"), "koichi", fields).await.unwrap();
+
+        let profile = store.get("koichi").await.unwrap();
+        assert_eq!(store.saved_page("koichi", &profile).await, None);
+    }
+}
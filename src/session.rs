@@ -0,0 +1,157 @@
+use crate::config::SessionConfig;
+use crate::UserToken;
+use axum_extra::extract::cookie::{Cookie, SameSite};
+use moka::future::Cache;
+use std::borrow::Cow;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Maps the id in someone's session cookie to the token they logged in with. This used to be a plain
+/// `HashMap` behind a `RwLock`, which meant a session lived for as long as the process did. Sessions
+/// now expire after `session.idle_timeout_secs` without being used, and are handed out fresh on every
+/// login so an id that was issued before someone logged in can't be used to ride along afterwards.
+#[derive(Clone)]
+pub struct SessionStore {
+    sessions: Cache<String, UserToken>,
+}
+
+impl SessionStore {
+    pub fn new(capacity: u64, idle_timeout: Duration) -> Self {
+        SessionStore {
+            sessions: Cache::builder()
+                .max_capacity(capacity)
+                .time_to_idle(idle_timeout)
+                .build(),
+        }
+    }
+
+    pub async fn get(&self, session_id: &str) -> Option<UserToken> {
+        self.sessions.get(session_id).await
+    }
+
+    /// Starts a new session for `token` and returns its id. If the browser already had a session,
+    /// pass its id as `previous` and it stops working.
+    pub async fn rotate(&self, previous: Option<&str>, token: UserToken) -> String {
+        if let Some(previous) = previous {
+            self.remove(previous).await;
+        }
+
+        let session_id = Uuid::new_v4().to_string();
+        self.sessions.insert(session_id.clone(), token).await;
+
+        session_id
+    }
+
+    pub async fn remove(&self, session_id: &str) {
+        self.sessions.invalidate(session_id).await;
+    }
+
+    /// Settles the sessions, for `AppState::flush_stores`.
+    pub async fn flush(&self) {
+        self.sessions.run_pending_tasks().await;
+    }
+
+    /// How many sessions are still live. Expired ones are cleared out first, since moka otherwise
+    /// keeps counting them until it gets around to its own housekeeping.
+    pub async fn len(&self) -> u64 {
+        self.sessions.run_pending_tasks().await;
+        self.sessions.entry_count()
+    }
+}
+
+/// Builds the session cookie with the attributes from the config. The cookie is always HttpOnly since
+/// nothing on the page has any business reading it.
+pub fn session_cookie(config: &SessionConfig, session_id: String) -> Cookie<'static> {
+    let mut cookie = site_cookie(config, config.cookie_name.clone(), session_id);
+
+    if let Some(max_age) = config.cookie_max_age_secs {
+        cookie.set_max_age(time::Duration::seconds(max_age as i64));
+    }
+
+    cookie
+}
+
+/// Builds any other cookie the site sets, like the CSRF token or a flash message, with the same HttpOnly,
+/// Secure, SameSite, path and domain attributes as the session cookie. They don't get its max age, so
+/// they go away when the browser closes.
+pub fn site_cookie(
+    config: &SessionConfig,
+    name: impl Into<Cow<'static, str>>,
+    value: impl Into<Cow<'static, str>>,
+) -> Cookie<'static> {
+    let same_site = match config.cookie_same_site.to_ascii_lowercase().as_str() {
+        "strict" => SameSite::Strict,
+        "none" => SameSite::None,
+        _ => SameSite::Lax,
+    };
+    let mut cookie = Cookie::build((name, value))
+        .http_only(true)
+        .secure(config.cookie_secure)
+        .same_site(same_site)
+        .path(config.cookie_path.clone());
+
+    if let Some(domain) = &config.cookie_domain {
+        cookie = cookie.domain(domain.clone());
+    }
+
+    cookie.build()
+}
+
+/// A cookie that matches the session cookie closely enough for the browser to delete it. The path
+/// and domain have to be the same as the ones it was set with.
+pub fn removal_cookie(config: &SessionConfig) -> Cookie<'static> {
+    site_removal_cookie(config, config.cookie_name.clone())
+}
+
+/// The same as `removal_cookie`, for a cookie made with `site_cookie`.
+pub fn site_removal_cookie(
+    config: &SessionConfig,
+    name: impl Into<Cow<'static, str>>,
+) -> Cookie<'static> {
+    let mut cookie = Cookie::build((name, "")).path(config.cookie_path.clone());
+
+    if let Some(domain) = &config.cookie_domain {
+        cookie = cookie.domain(domain.clone());
+    }
+
+    cookie.build()
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_rotate_invalidates_previous_session() {
+        let store = SessionStore::new(10, Duration::from_secs(60));
+        let token = UserToken {
+            token: "token".to_string(),
+        };
+
+        let first = store.rotate(None, token.clone()).await;
+        let second = store.rotate(Some(&first), token.clone()).await;
+
+        assert_ne!(first, second);
+        assert_eq!(store.get(&first).await, None);
+        assert_eq!(store.get(&second).await, Some(token));
+    }
+
+    #[test]
+    fn test_session_cookie_attributes() {
+        let config = SessionConfig {
+            cookie_secure: true,
+            cookie_same_site: "strict".to_string(),
+            cookie_max_age_secs: Some(60),
+            ..SessionConfig::default()
+        };
+
+        let cookie = session_cookie(&config, "id".to_string());
+
+        assert_eq!(cookie.http_only(), Some(true));
+        assert_eq!(cookie.secure(), Some(true));
+        assert_eq!(cookie.same_site(), Some(SameSite::Strict));
+        assert_eq!(cookie.path(), Some("/"));
+        assert_eq!(cookie.max_age(), Some(time::Duration::seconds(60)));
+    }
+}
@@ -0,0 +1,107 @@
+use axum::{
+    extract::{MatchedPath, Request},
+    middleware::Next,
+    response::Response,
+};
+use metrics_exporter_prometheus::{BuildError, PrometheusBuilder, PrometheusHandle};
+use std::time::Instant;
+use tracing::Span;
+
+/// Histogram buckets in seconds. Most pages are served from the cache in a few milliseconds, but a cold
+/// /info load or a long wait on the rate limiter can take the better part of a minute, so the buckets
+/// stretch out that far.
+const LATENCY_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0,
+];
+
+/// Installs the Prometheus recorder that every `metrics::counter!` and friends in the server and in
+/// `ApiClient` reports to, and returns the handle /metrics renders from. This can only be done once
+/// per process.
+pub fn install_recorder() -> Result<PrometheusHandle, BuildError> {
+    PrometheusBuilder::new()
+        .set_buckets(LATENCY_BUCKETS)?
+        .install_recorder()
+}
+
+/// Counts every request and how long it took, labelled by the route it matched rather than the raw
+/// path, so the number of time series stays fixed no matter what people type into the address bar.
+pub async fn track_requests(request: Request, next: Next) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let method = request.method().to_string();
+    let started = Instant::now();
+
+    let response = next.run(request).await;
+
+    metrics::counter!(
+        "http_requests_total",
+        "method" => method.clone(),
+        "route" => route.clone(),
+        "status" => response.status().as_u16().to_string()
+    )
+    .increment(1);
+    metrics::histogram!(
+        "http_request_duration_seconds",
+        "method" => method,
+        "route" => route
+    )
+    .record(started.elapsed().as_secs_f64());
+
+    response
+}
+
+/// The span every request runs in. Only the route it matched goes in, not the path, query string or any
+/// headers. Paths like /calendar/{file_name} and /groups/{id}/join/{invite} carry secrets that work
+/// as passwords, so this way nothing a user sends us ends up in the logs by accident.
+pub fn request_span(request: &Request) -> Span {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str())
+        .unwrap_or("unmatched");
+
+    tracing::info_span!(
+        "request",
+        method = %request.method(),
+        route,
+    )
+}
+
+/// Records whether a user's data was already in the cache when a page asked for it.
+pub fn record_cache_lookup(hit: bool) {
+    let result = if hit { "hit" } else { "miss" };
+
+    metrics::counter!("user_info_cache_requests_total", "result" => result).increment(1);
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use axum::{body::Body, middleware::from_fn, routing::get, Router};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_requests_are_labelled_by_route() {
+        let recorder = PrometheusBuilder::new().build_recorder();
+        let handle = recorder.handle();
+        let _guard = metrics::set_default_local_recorder(&recorder);
+
+        let app = Router::new()
+            .route("/things/{id}", get(|| async { "ok" }))
+            .layer(from_fn(track_requests));
+
+        app.oneshot(Request::get("/things/42").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        let rendered = handle.render();
+
+        assert!(rendered
+            .contains(r#"http_requests_total{method="GET",route="/things/{id}",status="200"} 1"#));
+        assert!(!rendered.contains("/things/42"));
+    }
+}